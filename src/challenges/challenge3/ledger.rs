@@ -0,0 +1,134 @@
+use std::collections::VecDeque;
+
+use axum::{
+    extract::{Query, State},
+    http::HeaderMap,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::types::chrono::{DateTime, Utc};
+
+use super::{units::Conversion, MilkState, LEDGER_CAPACITY};
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Withdrawal,
+    Rejection,
+    Refill,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct LedgerEntry {
+    timestamp: DateTime<Utc>,
+    client: String,
+    event: EventKind,
+    amount: usize,
+    conversion: Option<Conversion>,
+}
+
+#[derive(Default)]
+pub struct Ledger {
+    entries: VecDeque<LedgerEntry>,
+}
+
+impl Ledger {
+    pub fn record(
+        &mut self,
+        headers: &HeaderMap,
+        event: EventKind,
+        amount: usize,
+        conversion: Option<Conversion>,
+    ) {
+        // Drop the oldest entry once full
+        if self.entries.len() >= LEDGER_CAPACITY {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(LedgerEntry {
+            timestamp: Utc::now(),
+            client: client_id(headers),
+            event,
+            amount,
+            conversion,
+        });
+    }
+}
+
+fn client_id(headers: &HeaderMap) -> String {
+    // Clients identify themselves through a header, everyone else is anonymous
+    headers
+        .get("X-Client-Id")
+        .and_then(|id| id.to_str().ok())
+        .unwrap_or("anonymous")
+        .to_string()
+}
+
+#[derive(Deserialize)]
+pub struct HistoryQuery {
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    client: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct Summary {
+    withdrawals: usize,
+    rejections: usize,
+    refills: usize,
+    withdrawals_per_minute: f64,
+    rejection_rate: f64,
+}
+
+#[derive(Serialize)]
+pub struct HistoryResponse {
+    events: Vec<LedgerEntry>,
+    summary: Summary,
+}
+
+pub async fn history(
+    State(state): State<MilkState>,
+    Query(query): Query<HistoryQuery>,
+) -> Json<HistoryResponse> {
+    // Filter entries by time range and client
+    let ledger = state.ledger.lock().await;
+    let events: Vec<LedgerEntry> = ledger
+        .entries
+        .iter()
+        .filter(|e| query.from.is_none_or(|from| e.timestamp >= from))
+        .filter(|e| query.to.is_none_or(|to| e.timestamp <= to))
+        .filter(|e| query.client.as_ref().is_none_or(|c| &e.client == c))
+        .cloned()
+        .collect();
+    drop(ledger);
+
+    // Count each kind of event
+    let count = |kind| events.iter().filter(|e| e.event == kind).count();
+    let withdrawals = count(EventKind::Withdrawal);
+    let rejections = count(EventKind::Rejection);
+    let refills = count(EventKind::Refill);
+
+    // Measure rates over the requested window, or the span of the events
+    let start = query
+        .from
+        .or(events.first().map(|e| e.timestamp))
+        .unwrap_or_else(Utc::now);
+    let end = query.to.unwrap_or_else(Utc::now);
+    let minutes = ((end - start).num_milliseconds() as f64 / 60_000.0).max(1.0);
+    let attempts = withdrawals + rejections;
+    let rejection_rate = if attempts == 0 {
+        0.0
+    } else {
+        rejections as f64 / attempts as f64
+    };
+
+    Json(HistoryResponse {
+        events,
+        summary: Summary {
+            withdrawals,
+            rejections,
+            refills,
+            withdrawals_per_minute: withdrawals as f64 / minutes,
+            rejection_rate,
+        },
+    })
+}
@@ -1,47 +1,151 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use axum::{
     extract::State,
-    http::{HeaderMap, StatusCode},
+    http::{header::RETRY_AFTER, HeaderMap, StatusCode},
     response::{IntoResponse, Response},
+    Json,
 };
-use leaky_bucket::RateLimiter;
-use tokio::sync::Mutex;
+use serde::Deserialize;
+use serde_json::{Map, Value};
 
-use super::{new_limiter, units::convert_units};
+use super::{
+    ledger::EventKind,
+    units::{convert_units, Conversion},
+    Bucket, MilkState, MAX_WAIT,
+};
+
+// Body keys that control the withdrawal rather than name an amount to convert
+const WITHDRAW_KEYS: &[&str] = &["withdraw", "wait_ms"];
+
+#[derive(Deserialize, Default)]
+struct WithdrawRequest {
+    // Number of tokens to consume, defaults to 1
+    withdraw: Option<usize>,
+    // Wait up to this many milliseconds for tokens instead of failing
+    wait_ms: Option<u64>,
+}
+
+fn wait_estimate(bucket: &Bucket, permits: usize) -> Duration {
+    // Time until enough refills have happened to cover the missing tokens,
+    // the next one being due one interval after the last
+    let limiter = &bucket.limiter;
+    let missing = permits.saturating_sub(limiter.balance());
+    let refills = missing.div_ceil(limiter.refill());
+    (limiter.interval() * refills as u32).saturating_sub(bucket.since_refill())
+}
+
+fn bad_request() -> (StatusCode, Response) {
+    (
+        StatusCode::BAD_REQUEST,
+        "Invalid withdrawal\n".to_string().into_response(),
+    )
+}
 
 pub async fn get_milk(
     headers: HeaderMap,
-    State(limiter): State<Arc<Mutex<RateLimiter>>>,
+    State(state): State<MilkState>,
     body: String,
 ) -> (StatusCode, Response) {
-    let limiter = limiter.lock().await;
-    // Try to acquire a token from the rate limiter
-    if limiter.try_acquire(1) {
-        if let Some(content_type) = headers.get("Content-Type") {
-            if content_type == "application/json" {
-                // Convert the units
-                return convert_units(body);
+    // Read the withdrawal from a JSON body, converting units if it names any
+    let mut request = WithdrawRequest::default();
+    let mut conversion: Option<Conversion> = None;
+    if headers
+        .get("Content-Type")
+        .is_some_and(|content_type| content_type == "application/json")
+    {
+        let Ok(fields) = serde_json::from_str::<Map<String, Value>>(&body) else {
+            return bad_request();
+        };
+        let options = Map::from_iter(
+            fields
+                .iter()
+                .filter(|(key, _)| WITHDRAW_KEYS.contains(&key.as_str()))
+                .map(|(key, value)| (key.clone(), value.clone())),
+        );
+        let Ok(parsed) = serde_json::from_value(Value::Object(options.clone())) else {
+            return bad_request();
+        };
+        request = parsed;
+
+        // A body with only withdrawal options has nothing to convert
+        if options.is_empty() || options.len() < fields.len() {
+            match convert_units(&body) {
+                Ok(converted) => conversion = Some(converted),
+                Err(status) => return (status, Response::default()),
             }
         }
+    }
+
+    // Clone the current limiter so waiting does not block refills
+    let bucket = state.limiter.lock().await.clone();
+    let limiter = &bucket.limiter;
+
+    // Check that the withdrawal can ever be satisfied
+    let permits = request.withdraw.unwrap_or(1);
+    if permits == 0 || permits > limiter.max() {
+        return bad_request();
+    }
+
+    // Try to acquire the tokens from the rate limiter, waiting if requested
+    let acquired = match request.wait_ms {
+        Some(wait_ms) => {
+            let wait = Duration::from_millis(wait_ms).min(MAX_WAIT);
+            tokio::time::timeout(wait, limiter.acquire(permits))
+                .await
+                .is_ok()
+        }
+        None => limiter.try_acquire(permits),
+    };
+
+    if !acquired {
+        state
+            .ledger
+            .lock()
+            .await
+            .record(&headers, EventKind::Rejection, permits, None);
+
+        // Rate limit exceeded, tell the client when to come back
+        let wait = wait_estimate(&bucket, permits);
+        let retry_after = wait.as_secs_f64().ceil().to_string();
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            ([(RETRY_AFTER, retry_after)], "No milk available\n").into_response(),
+        );
+    }
+
+    state
+        .ledger
+        .lock()
+        .await
+        .record(&headers, EventKind::Withdrawal, permits, conversion.clone());
+
+    match conversion {
+        Some(conversion) => (StatusCode::OK, Json(conversion.to_json()).into_response()),
         // Success
-        (
+        None => (
             StatusCode::OK,
             "Milk withdrawn\n".to_string().into_response(),
-        )
-    } else {
-        // Rate limit exceeded
-        (
-            StatusCode::TOO_MANY_REQUESTS,
-            "No milk available\n".to_string().into_response(),
-        )
+        ),
     }
 }
 
-pub async fn refill_milk(State(limiter): State<Arc<Mutex<RateLimiter>>>) -> StatusCode {
+pub async fn refill_milk(headers: HeaderMap, State(state): State<MilkState>) -> StatusCode {
     // Reset the rate limiter
     // This is a bit of a hack, but it's the only way to change the rate limiter
-    let mut limiter = limiter.lock().await;
-    *limiter = new_limiter();
+    let mut bucket = state.limiter.lock().await;
+
+    // The balance is only updated when tokens are taken, so bring it up to date
+    // with a request larger than the bucket, which never succeeds
+    let previous = &bucket.limiter;
+    previous.try_acquire(previous.max() + 1);
+    let restored = previous.max().saturating_sub(previous.balance());
+
+    *bucket = Arc::new(Bucket::new());
+    state
+        .ledger
+        .lock()
+        .await
+        .record(&headers, EventKind::Refill, restored, None);
     StatusCode::OK
 }
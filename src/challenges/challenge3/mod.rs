@@ -1,17 +1,89 @@
-use axum::{routing::post, Router};
+use axum::{
+    routing::{get, post},
+    Router,
+};
 use leaky_bucket::RateLimiter;
+use ledger::{history, Ledger};
 use milk::{get_milk, refill_milk};
-use std::{sync::Arc, time::Duration};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use tokio::sync::Mutex;
 
+mod ledger;
 mod milk;
 mod units;
 
-// Conversion factors
-const LITERS_PER_GALLON: f32 = 3.78541; // Liters per US gallon
-const GALLONS_PER_LITER: f32 = 0.264172060; // US gallons per liter
-const PINTS_PER_LITRE: f32 = 1.759754; // UK pints per liter
-const LITRES_PER_PINT: f32 = 0.56826125; // Liters per UK pint
+// Conversion factors, as exact litres per unit
+// Unqualified pints are UK pints, unqualified quarts and gallons are US
+const LITRES_PER_UNIT: &[(&str, f64)] = &[
+    ("ml", 0.001),
+    ("cl", 0.01),
+    ("dl", 0.1),
+    ("l", 1.0),
+    ("liters", 1.0),
+    ("litres", 1.0),
+    ("cubic_meters", 1000.0),
+    ("cubic_metres", 1000.0),
+    ("us_fl_oz", 0.029_573_529_562_5),
+    ("uk_fl_oz", 0.028_413_062_5),
+    ("cups", 0.236_588_236_5),
+    ("pints", 0.568_261_25),
+    ("uk_pints", 0.568_261_25),
+    ("us_pints", 0.473_176_473),
+    ("quarts", 0.946_352_946),
+    ("us_quarts", 0.946_352_946),
+    ("uk_quarts", 1.136_522_5),
+    ("gallons", 3.785_411_784),
+    ("us_gallons", 3.785_411_784),
+    ("uk_gallons", 4.546_09),
+    ("tablespoons", 0.014_786_764_781_25),
+    ("teaspoons", 0.004_928_921_593_75),
+];
+
+// Target unit used when a request does not name one
+const DEFAULT_TARGETS: &[(&str, &str)] = &[
+    ("gallons", "liters"),
+    ("liters", "gallons"),
+    ("litres", "pints"),
+    ("pints", "litres"),
+];
+
+// Longest time a client may ask to wait for milk
+const MAX_WAIT: Duration = Duration::from_secs(10);
+
+// Most events kept in the ledger before the oldest are dropped
+const LEDGER_CAPACITY: usize = 10_000;
+
+struct MilkStateInner {
+    // Wrapped in an extra Arc so handlers can wait on it without holding the lock
+    limiter: Mutex<Arc<Bucket>>,
+    ledger: Mutex<Ledger>,
+}
+
+type MilkState = Arc<MilkStateInner>;
+
+/// The rate limiter and when it was built, it refills once every interval after that
+struct Bucket {
+    limiter: RateLimiter,
+    created: Instant,
+}
+
+impl Bucket {
+    fn new() -> Bucket {
+        Bucket {
+            limiter: new_limiter(),
+            created: Instant::now(),
+        }
+    }
+
+    /// Time since the limiter last added tokens
+    fn since_refill(&self) -> Duration {
+        let interval = self.limiter.interval().as_nanos();
+        Duration::from_nanos((self.created.elapsed().as_nanos() % interval) as u64)
+    }
+}
 
 pub fn new_limiter() -> RateLimiter {
     RateLimiter::builder()
@@ -23,12 +95,16 @@ pub fn new_limiter() -> RateLimiter {
 }
 
 pub fn router() -> Router {
-    // Create the limiter, wrapped in an Arc and Mutex
-    let limiter = Arc::new(Mutex::new(new_limiter()));
+    // Create the limiter and ledger, wrapped in an Arc and Mutex
+    let state = Arc::new(MilkStateInner {
+        limiter: Mutex::new(Arc::new(Bucket::new())),
+        ledger: Mutex::new(Ledger::default()),
+    });
 
     // Create the router
     Router::new()
         .route("/milk", post(get_milk))
         .route("/refill", post(refill_milk))
-        .with_state(limiter)
+        .route("/history", get(history))
+        .with_state(state)
 }
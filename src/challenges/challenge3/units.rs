@@ -1,74 +1,209 @@
-use axum::{
-    body::Body,
-    http::{Response, StatusCode},
-    response::IntoResponse,
-    Json,
-};
-use serde::Deserialize;
-use serde_json::json;
+use axum::http::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
 
-use super::{GALLONS_PER_LITER, LITERS_PER_GALLON, LITRES_PER_PINT, PINTS_PER_LITRE};
+use super::{DEFAULT_TARGETS, LITRES_PER_UNIT};
+
+// Most decimals worth rounding to, an f64 holds about 15 to 17 significant digits
+const MAX_DECIMALS: u32 = 15;
+const MAX_SIGNIFICANT_FIGURES: u32 = 17;
 
 #[derive(Deserialize)]
-struct VolumeUnits {
-    gallons: Option<f32>,
-    liters: Option<f32>,
-    litres: Option<f32>,
-    pints: Option<f32>,
+struct ConversionRequest {
+    // Unit to convert into, falls back to DEFAULT_TARGETS if missing
+    to: Option<String>,
+    // Round the result to this many decimal places
+    decimals: Option<u32>,
+    // Round the result to this many significant figures
+    significant_figures: Option<u32>,
+    // Remaining keys, exactly one of which must be a known unit
+    #[serde(flatten)]
+    amounts: Map<String, Value>,
+}
+
+fn litres_per_unit(unit: &str) -> Option<f64> {
+    LITRES_PER_UNIT
+        .iter()
+        .find(|(name, _)| *name == unit)
+        .map(|(_, litres)| *litres)
+}
+
+fn default_target(unit: &str) -> Option<&'static str> {
+    DEFAULT_TARGETS
+        .iter()
+        .find(|(from, _)| *from == unit)
+        .map(|(_, to)| *to)
+}
+
+/// Converts `amount` of `from` into `to`, going through litres.
+///
+/// Uses `f64` throughout, so a round trip between any two units stays
+/// within a relative error of 1e-12 of the original amount.
+fn convert(amount: f64, from: &str, to: &str) -> Option<f64> {
+    let litres = amount * litres_per_unit(from)?;
+    Some(litres / litres_per_unit(to)?)
+}
+
+fn round_decimals(value: f64, decimals: u32) -> f64 {
+    let factor = 10f64.powi(decimals as i32);
+    let scaled = value * factor;
+    // Values this large have no fractional digits left to round
+    if !scaled.is_finite() {
+        return value;
+    }
+    scaled.round() / factor
+}
+
+fn round_significant(value: f64, figures: u32) -> f64 {
+    if value == 0.0 || !value.is_finite() {
+        return value;
+    }
+    // Number of decimals needed to keep the given significant figures
+    let magnitude = value.abs().log10().floor() as i32 + 1;
+    let decimals = figures as i32 - magnitude;
+    let factor = 10f64.powi(decimals);
+    let scaled = value * factor;
+    // Tiny values need more decimals than an f64 can scale by, and already fit
+    if !factor.is_finite() || factor == 0.0 || !scaled.is_finite() {
+        return value;
+    }
+    scaled.round() / factor
+}
+
+/// A successful conversion, as recorded in the ledger
+#[derive(Serialize, Clone, Debug)]
+pub struct Conversion {
+    pub from: String,
+    pub amount: f64,
+    pub to: String,
+    pub converted: f64,
+}
+
+impl Conversion {
+    pub fn to_json(&self) -> Value {
+        let mut response = Map::new();
+        response.insert(self.to.clone(), json!(self.converted));
+        Value::Object(response)
+    }
 }
 
-pub fn convert_units(body_str: String) -> (StatusCode, Response<Body>) {
+pub fn convert_units(body_str: &str) -> Result<Conversion, StatusCode> {
     // Parse the body as JSON into a struct
-    let body = serde_json::from_str::<VolumeUnits>(&body_str);
-    if body.is_err() {
-        return (StatusCode::BAD_REQUEST, Response::default());
+    let body =
+        serde_json::from_str::<ConversionRequest>(body_str).map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    // Only one rounding mode can be used at a time
+    if body.decimals.is_some() && body.significant_figures.is_some() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    // Reject precisions an f64 can not represent
+    if body
+        .decimals
+        .is_some_and(|decimals| decimals > MAX_DECIMALS)
+    {
+        return Err(StatusCode::BAD_REQUEST);
     }
-    let body = body.unwrap();
-
-    // Check if more than 1 keys are provided
-    let mut count = 0;
-    count += body.gallons.map(|_| 1).unwrap_or(0);
-    count += body.liters.map(|_| 1).unwrap_or(0);
-    count += body.litres.map(|_| 1).unwrap_or(0);
-    count += body.pints.map(|_| 1).unwrap_or(0);
-    if count != 1 {
-        return (StatusCode::BAD_REQUEST, Response::default());
+    if body
+        .significant_figures
+        .is_some_and(|figures| !(1..=MAX_SIGNIFICANT_FIGURES).contains(&figures))
+    {
+        return Err(StatusCode::BAD_REQUEST);
     }
 
+    // Check that exactly 1 known unit is provided
+    let mut sources = body
+        .amounts
+        .iter()
+        .filter(|(unit, _)| litres_per_unit(unit).is_some());
+    let (source, amount) = match (sources.next(), sources.next()) {
+        (Some(source), None) => source,
+        _ => return Err(StatusCode::BAD_REQUEST),
+    };
+    let amount = amount.as_f64().ok_or(StatusCode::BAD_REQUEST)?;
+
+    // Find the target unit
+    let target = match body.to.as_deref() {
+        Some(target) => target,
+        None => default_target(source).ok_or(StatusCode::BAD_REQUEST)?,
+    };
+
     // Convert the units
-    if let Some(gallons) = body.gallons {
-        // Gallons to liters
-        let liters = gallons * LITERS_PER_GALLON;
-        return (
-            StatusCode::OK,
-            Json::from(json!({"liters": liters })).into_response(),
-        );
+    let mut converted = convert(amount, source, target).ok_or(StatusCode::BAD_REQUEST)?;
+    if let Some(decimals) = body.decimals {
+        converted = round_decimals(converted, decimals);
     }
-    if let Some(liters) = body.liters {
-        // Liters to gallons
-        let gallons = liters * GALLONS_PER_LITER;
-        return (
-            StatusCode::OK,
-            Json::from(json!({"gallons": gallons })).into_response(),
-        );
+    if let Some(figures) = body.significant_figures {
+        converted = round_significant(converted, figures);
     }
-    if let Some(litres) = body.litres {
-        // Litres to pints
-        let pints = litres * PINTS_PER_LITRE;
-        return (
-            StatusCode::OK,
-            Json::from(json!({"pints": pints })).into_response(),
+
+    Ok(Conversion {
+        from: source.to_string(),
+        amount,
+        to: target.to_string(),
+        converted,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    const TOLERANCE: f64 = 1e-12;
+
+    fn assert_close(original: f64, round_trip: f64) {
+        // Relative error, as promised by `convert`, also for amounts below 1
+        let error = (original - round_trip).abs();
+        assert!(
+            error <= TOLERANCE * original.abs(),
+            "{original} became {round_trip}"
         );
     }
-    if let Some(pints) = body.pints {
-        // Pints to litres
-        let litres = pints * LITRES_PER_PINT;
-        return (
-            StatusCode::OK,
-            Json::from(json!({"litres": litres })).into_response(),
-        );
+
+    proptest! {
+        #[test]
+        fn gallons_liters_round_trip(gallons in -1e12f64..1e12) {
+            let liters = convert(gallons, "gallons", "liters").unwrap();
+            let back = convert(liters, "liters", "gallons").unwrap();
+            assert_close(gallons, back);
+        }
+
+        #[test]
+        fn any_unit_round_trip(
+            amount in -1e9f64..1e9,
+            from in 0..LITRES_PER_UNIT.len(),
+            to in 0..LITRES_PER_UNIT.len(),
+        ) {
+            let (from, _) = LITRES_PER_UNIT[from];
+            let (to, _) = LITRES_PER_UNIT[to];
+            let converted = convert(amount, from, to).unwrap();
+            let back = convert(converted, to, from).unwrap();
+            assert_close(amount, back);
+        }
     }
 
-    // This should never be reached
-    (StatusCode::BAD_REQUEST, Response::default())
+    #[test]
+    fn significant_figures() {
+        assert_eq!(round_significant(3.785411784, 3), 3.79);
+        assert_eq!(round_significant(1234.5, 2), 1200.0);
+        assert_eq!(round_significant(-0.0012345, 2), -0.0012);
+    }
+
+    #[test]
+    fn decimals() {
+        assert_eq!(round_decimals(3.785411784, 2), 3.79);
+        assert_eq!(round_decimals(0.264172052, 4), 0.2642);
+    }
+
+    #[test]
+    fn extreme_precision() {
+        assert_eq!(round_decimals(1e300, MAX_DECIMALS), 1e300);
+        assert_eq!(round_significant(1e-300, MAX_SIGNIFICANT_FIGURES), 1e-300);
+        let request = |rounding: &str| convert_units(&format!(r#"{{"liters": 1, {rounding}}}"#));
+        assert!(request(r#""decimals": 16"#).is_err());
+        assert!(request(r#""decimals": 4294967295"#).is_err());
+        assert!(request(r#""significant_figures": 18"#).is_err());
+        assert!(request(r#""significant_figures": 0"#).is_err());
+        assert!(request(r#""significant_figures": 17"#).is_ok());
+    }
 }
@@ -0,0 +1,393 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use tokio::task::spawn_blocking;
+
+use super::{
+    board::{Board, BoardTile, Team},
+    db::record_if_finished,
+    endpoints::place_error,
+    games::{check_player, CurrentGame},
+    AppState, DEFAULT_AI_DEPTH, MAX_AI_DEPTH, MAX_AI_NODES,
+};
+
+// Score of a won position, larger than any heuristic evaluation
+const WIN_SCORE: i64 = 1_000_000;
+
+#[derive(Deserialize)]
+pub struct TeamPath {
+    pub team: Team,
+}
+
+#[derive(Deserialize)]
+pub struct DepthQuery {
+    // Number of moves to look ahead
+    depth: Option<u32>,
+}
+
+#[derive(Serialize)]
+pub struct Hint {
+    column: usize,
+    evaluation: i64,
+}
+
+fn index(team: Team) -> usize {
+    match team {
+        Team::Cookie => 0,
+        Team::Milk => 1,
+    }
+}
+
+fn line_score(counts: [usize; 2]) -> i64 {
+    // Reward lines only one team can still complete, more so the fuller they are
+    match counts {
+        [cookies, 0] => (cookies * cookies) as i64,
+        [0, milk] => -((milk * milk) as i64),
+        _ => 0,
+    }
+}
+
+/// Board state for the search, updated in place as moves are tried and taken back
+struct Search {
+    width: usize,
+    win_length: usize,
+    // Grid of tiles, top row first
+    tiles: Vec<Vec<BoardTile>>,
+    // Tiles of each team on every line, and the lines through each tile
+    counts: Vec<[usize; 2]>,
+    lines_at: Vec<Vec<Vec<usize>>>,
+    // Sum of line scores from cookie's point of view
+    score: i64,
+    // Completed lines of each team
+    wins: [usize; 2],
+    nodes: u64,
+}
+
+impl Search {
+    fn new(board: &Board) -> Search {
+        let size = board.size();
+        let mut search = Search {
+            width: size.width,
+            win_length: size.win_length,
+            tiles: vec![vec![BoardTile::Empty; size.width]; size.height],
+            counts: Vec::new(),
+            lines_at: vec![vec![Vec::new(); size.width]; size.height],
+            score: 0,
+            wins: [0, 0],
+            nodes: 0,
+        };
+        for (line, cells) in board.lines().into_iter().enumerate() {
+            search.counts.push([0, 0]);
+            for (i, j) in cells {
+                search.lines_at[i][j].push(line);
+            }
+        }
+
+        // Account for the tiles already on the board
+        for (i, row) in board.tiles().iter().enumerate() {
+            for (j, tile) in row.iter().enumerate() {
+                if let Some(team) = tile.team() {
+                    search.set(i, j, team);
+                }
+            }
+        }
+        search
+    }
+
+    fn set(&mut self, i: usize, j: usize, team: Team) {
+        self.tiles[i][j] = team.tile();
+        for &line in &self.lines_at[i][j] {
+            self.score -= line_score(self.counts[line]);
+            self.counts[line][index(team)] += 1;
+            self.score += line_score(self.counts[line]);
+            if self.counts[line][index(team)] == self.win_length {
+                self.wins[index(team)] += 1;
+            }
+        }
+    }
+
+    fn clear(&mut self, i: usize, j: usize, team: Team) {
+        self.tiles[i][j] = BoardTile::Empty;
+        for &line in &self.lines_at[i][j] {
+            if self.counts[line][index(team)] == self.win_length {
+                self.wins[index(team)] -= 1;
+            }
+            self.score -= line_score(self.counts[line]);
+            self.counts[line][index(team)] -= 1;
+            self.score += line_score(self.counts[line]);
+        }
+    }
+
+    fn play(&mut self, team: Team, col: usize) -> Option<usize> {
+        // Drop the tile into the lowest empty row of the column
+        let i = (0..self.tiles.len())
+            .rev()
+            .find(|i| self.tiles[*i][col - 1].is_empty())?;
+        self.set(i, col - 1, team);
+        Some(i)
+    }
+
+    fn evaluate(&self, team: Team) -> i64 {
+        match team {
+            Team::Cookie => self.score,
+            Team::Milk => -self.score,
+        }
+    }
+
+    fn columns(&self) -> Vec<usize> {
+        // Try central columns first, they tend to be better and prune more
+        let width = self.width;
+        let mut columns: Vec<usize> = (1..=width).collect();
+        columns.sort_by_key(|c| (2 * *c as isize - width as isize - 1).abs());
+        columns
+    }
+
+    /// Best score `team` can reach, or None once the node budget is used up
+    fn minimax(&mut self, team: Team, depth: u32, mut alpha: i64, beta: i64) -> Option<i64> {
+        self.nodes += 1;
+        if self.nodes > MAX_AI_NODES {
+            return None;
+        }
+        if depth == 0 {
+            return Some(self.evaluate(team));
+        }
+
+        let mut best = None;
+        for col in self.columns() {
+            let Some(row) = self.play(team, col) else {
+                continue;
+            };
+            // Prefer quicker wins and slower losses
+            let score = if self.wins[index(team)] > 0 {
+                Some(WIN_SCORE + depth as i64)
+            } else {
+                self.minimax(team.other(), depth - 1, -beta, -alpha)
+                    .map(|score| -score)
+            };
+            self.clear(row, col - 1, team);
+            let score = score?;
+            best = Some(best.map_or(score, |best: i64| best.max(score)));
+            alpha = alpha.max(score);
+            if alpha >= beta {
+                break;
+            }
+        }
+
+        // A full board is a draw, scored by what is on it
+        Some(best.unwrap_or_else(|| self.evaluate(team)))
+    }
+
+    fn best_move(&mut self, team: Team, depth: u32) -> Option<Hint> {
+        let mut best: Option<Hint> = None;
+        let mut alpha = -WIN_SCORE * 2;
+        for col in self.columns() {
+            let Some(row) = self.play(team, col) else {
+                continue;
+            };
+            let score = if self.wins[index(team)] > 0 {
+                Some(WIN_SCORE + depth as i64)
+            } else {
+                self.minimax(
+                    team.other(),
+                    depth.saturating_sub(1),
+                    -WIN_SCORE * 2,
+                    -alpha,
+                )
+                .map(|score| -score)
+            };
+            self.clear(row, col - 1, team);
+            let score = score?;
+            if best.as_ref().is_none_or(|b| score > b.evaluation) {
+                best = Some(Hint {
+                    column: col,
+                    evaluation: score,
+                });
+            }
+            alpha = alpha.max(score);
+        }
+        best
+    }
+}
+
+/// Finds the best column for `team` using minimax with alpha-beta pruning
+///
+/// Searches one move deeper at a time until `depth` or the node budget is reached,
+/// keeping the result of the deepest search that finished
+pub fn best_move(board: &Board, team: Team, depth: u32) -> Option<Hint> {
+    // Looking further ahead than there are empty tiles finds nothing new
+    let empty = board
+        .tiles()
+        .iter()
+        .flatten()
+        .filter(|t| t.is_empty())
+        .count();
+    let depth = depth.clamp(1, empty.max(1) as u32);
+
+    let mut search = Search::new(board);
+    let mut best = None;
+    for depth in 1..=depth {
+        match search.best_move(team, depth) {
+            Some(hint) => best = Some(hint),
+            None => break,
+        }
+    }
+    best
+}
+
+async fn search(
+    board: Board,
+    team: Team,
+    depth: u32,
+) -> Result<Option<Hint>, (StatusCode, String)> {
+    // The search can take a while, so keep it off the async workers
+    spawn_blocking(move || best_move(&board, team, depth))
+        .await
+        .map_err(|err| {
+            eprintln!("{:?}", err);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "AI search failed\n".to_string(),
+            )
+        })
+}
+
+pub async fn hint(
+    CurrentGame(state): CurrentGame,
+    Path(TeamPath { team }): Path<TeamPath>,
+    Query(query): Query<DepthQuery>,
+) -> Result<Json<Hint>, (StatusCode, String)> {
+    let depth = query.depth.unwrap_or(DEFAULT_AI_DEPTH).min(MAX_AI_DEPTH);
+
+    // Check if board is already complete, then search on a copy without holding the lock
+    let board = state.read().await.board.clone();
+    if board.get_result().is_some() {
+        return Err((StatusCode::SERVICE_UNAVAILABLE, board.to_string()));
+    }
+
+    search(board.clone(), team, depth)
+        .await?
+        .map(Json)
+        .ok_or((StatusCode::SERVICE_UNAVAILABLE, board.to_string()))
+}
+
+pub async fn ai_move(
+    State(app): State<AppState>,
+    CurrentGame(state): CurrentGame,
+    Path(TeamPath { team }): Path<TeamPath>,
+    Query(query): Query<DepthQuery>,
+    headers: HeaderMap,
+) -> (StatusCode, String) {
+    let depth = query.depth.unwrap_or(DEFAULT_AI_DEPTH).min(MAX_AI_DEPTH);
+
+    // The AI can not take over a team that a player has joined
+    let f_state = state.read().await;
+    if f_state.board.is_strict()
+        && f_state.players.contains_key(&team)
+        && !check_player(&headers, &f_state.players, team)
+    {
+        return (StatusCode::FORBIDDEN, "Invalid player token\n".to_string());
+    }
+
+    // Check if board is already complete
+    let board = f_state.board.clone();
+    drop(f_state);
+    if board.get_result().is_some() {
+        return (StatusCode::SERVICE_UNAVAILABLE, board.to_string());
+    }
+
+    // Find the best column on a copy of the board
+    let hint = match search(board.clone(), team, depth).await {
+        Ok(Some(hint)) => hint,
+        Ok(None) => return (StatusCode::SERVICE_UNAVAILABLE, board.to_string()),
+        Err(err) => return err,
+    };
+
+    // Play it, unless the board changed while searching
+    let mut f_state = state.write().await;
+    if f_state.board.moves().len() != board.moves().len() || f_state.board.tiles() != board.tiles()
+    {
+        return (
+            StatusCode::CONFLICT,
+            "Board changed while thinking, try again\n".to_string(),
+        );
+    }
+    let board = &mut f_state.board;
+    match board.place_tile(team, hint.column) {
+        Ok(()) => {
+            f_state.notify();
+            record_if_finished(&app.pool, &mut f_state);
+            (StatusCode::OK, f_state.board.to_string())
+        }
+        Err(err) => place_error(board, err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::challenges::challenge4::board::BoardSize;
+
+    fn board(width: usize, height: usize, win_length: usize, notation: &str) -> Board {
+        let size = BoardSize {
+            width,
+            height,
+            win_length,
+        };
+        Board::import(size, false, notation).unwrap()
+    }
+
+    #[test]
+    fn takes_immediate_win() {
+        let board = board(4, 4, 4, "c1 m2 c1 m2 c1 m3");
+        let hint = best_move(&board, Team::Cookie, DEFAULT_AI_DEPTH).unwrap();
+        assert_eq!(hint.column, 1);
+        assert!(hint.evaluation >= WIN_SCORE);
+    }
+
+    #[test]
+    fn blocks_immediate_win() {
+        let board = board(4, 4, 4, "c1 m2 c1 m2 c1");
+        let hint = best_move(&board, Team::Milk, DEFAULT_AI_DEPTH).unwrap();
+        assert_eq!(hint.column, 1);
+    }
+
+    #[test]
+    fn connect_four() {
+        // Cookie completes a rising diagonal in column 4
+        let board = board(7, 6, 4, "c1 m2 c2 m3 m3 c3 m4 m4 m4");
+        let hint = best_move(&board, Team::Cookie, MAX_AI_DEPTH).unwrap();
+        assert_eq!(hint.column, 4);
+        assert!(hint.evaluation >= WIN_SCORE);
+    }
+
+    #[test]
+    fn incremental_state() {
+        // Playing and taking back a tile matches a search built from scratch
+        let board = board(7, 6, 4, "c4 m4 c3 m5 c2");
+        let mut search = Search::new(&board);
+        let (score, wins) = (search.score, search.wins);
+        for col in 1..=7 {
+            let row = search.play(Team::Milk, col).unwrap();
+            let mut played = board.clone();
+            played.place_tile(Team::Milk, col).unwrap();
+            let fresh = Search::new(&played);
+            assert_eq!((search.score, search.wins), (fresh.score, fresh.wins));
+            assert_eq!(search.counts, fresh.counts);
+            search.clear(row, col - 1, Team::Milk);
+        }
+        assert_eq!((search.score, search.wins), (score, wins));
+    }
+
+    #[test]
+    fn node_budget() {
+        let board = board(16, 16, 4, "c8 m9");
+        let mut search = Search::new(&board);
+        search.nodes = MAX_AI_NODES;
+        assert_eq!(search.minimax(Team::Cookie, 2, -WIN_SCORE, WIN_SCORE), None);
+        // The deepest search that finished in time still gives a move
+        assert!(best_move(&board, Team::Cookie, MAX_AI_DEPTH).is_some());
+    }
+}
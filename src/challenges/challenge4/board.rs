@@ -1,7 +1,15 @@
+use std::{fmt, str::FromStr};
+
 use rand::{rngs::StdRng, Rng};
+use serde::{de, Deserialize, Deserializer, Serialize};
+use sqlx::types::chrono::{DateTime, Utc};
 
-#[derive(Copy, Clone, Debug, PartialEq)]
-enum BoardTile {
+// Largest width or height a board can have
+const MAX_SIZE: usize = 16;
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BoardTile {
     Milk,
     Cookie,
     Empty,
@@ -10,10 +18,13 @@ enum BoardTile {
 impl BoardTile {
     pub fn is_empty(&self) -> bool {
         // Check weather the current tile is empty
-        if let BoardTile::Empty = self {
-            true
-        } else {
-            false
+        matches!(self, BoardTile::Empty)
+    }
+    pub fn team(&self) -> Option<Team> {
+        match self {
+            BoardTile::Cookie => Some(Team::Cookie),
+            BoardTile::Milk => Some(Team::Milk),
+            BoardTile::Empty => None,
         }
     }
     pub fn emoji(&self) -> char {
@@ -25,61 +36,315 @@ impl BoardTile {
     }
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Team {
+    Cookie,
+    Milk,
+}
+
+impl Team {
+    pub fn other(&self) -> Team {
+        match self {
+            Team::Cookie => Team::Milk,
+            Team::Milk => Team::Cookie,
+        }
+    }
+    pub fn tile(&self) -> BoardTile {
+        match self {
+            Team::Cookie => BoardTile::Cookie,
+            Team::Milk => BoardTile::Milk,
+        }
+    }
+    pub fn letter(&self) -> char {
+        match self {
+            Team::Cookie => 'c',
+            Team::Milk => 'm',
+        }
+    }
+}
+
+impl fmt::Display for Team {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Team::Cookie => f.write_str("cookie"),
+            Team::Milk => f.write_str("milk"),
+        }
+    }
+}
+
+impl FromStr for Team {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cookie" => Ok(Team::Cookie),
+            "milk" => Ok(Team::Milk),
+            _ => Err("Invalid team: ".to_string() + s),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Team {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // Parse through FromStr so path, query and JSON values accept the same names
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+/// Reasons a tile can not be placed
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PlaceError {
+    InvalidColumn,
+    ColumnFull,
+    NotYourTurn,
+}
+
+impl fmt::Display for PlaceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlaceError::InvalidColumn => f.write_str("Invalid column"),
+            PlaceError::ColumnFull => f.write_str("Column full"),
+            PlaceError::NotYourTurn => f.write_str("Not your turn"),
+        }
+    }
+}
+
+/// Dimensions of a board and the number of tiles in a row needed to win
+#[derive(Copy, Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct BoardSize {
+    pub width: usize,
+    pub height: usize,
+    pub win_length: usize,
+}
+
+impl Default for BoardSize {
+    fn default() -> Self {
+        BoardSize {
+            width: 4,
+            height: 4,
+            win_length: 4,
+        }
+    }
+}
+
+impl BoardSize {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(1..=MAX_SIZE).contains(&self.width) || !(1..=MAX_SIZE).contains(&self.height) {
+            return Err("Invalid size".to_string());
+        }
+        if !(1..=self.width.max(self.height)).contains(&self.win_length) {
+            return Err("Invalid win length".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// A tile placed on the board, columns and rows counted from 1 at the bottom left
+#[derive(Clone, Debug, Serialize)]
+pub struct Move {
+    pub team: Team,
+    pub column: usize,
+    pub row: usize,
+    pub timestamp: DateTime<Utc>,
+}
+
+// Directions to scan for a winning line, as (row, column) steps
+const DIRECTIONS: [(isize, isize, Direction); 4] = [
+    (0, 1, Direction::Row),
+    (1, 0, Direction::Column),
+    (1, 1, Direction::FallingDiagonal),
+    (1, -1, Direction::RisingDiagonal),
+];
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    Row,
+    Column,
+    RisingDiagonal,
+    FallingDiagonal,
+}
+
+/// A tile position, columns and rows counted from 1 at the bottom left
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+pub struct Position {
+    pub column: usize,
+    pub row: usize,
+}
+
+/// An unbroken run of at least win_length tiles of one team
+#[derive(Clone, Debug, Serialize)]
+pub struct WinningLine {
+    pub team: Team,
+    pub direction: Direction,
+    pub tiles: Vec<Position>,
+}
+
 #[derive(Clone, Debug)]
 pub struct Board {
-    // 4x4 grid of BoardTiles
-    board: [[BoardTile; 4]; 4],
+    size: BoardSize,
+    // Grid of BoardTiles, top row first
+    board: Vec<Vec<BoardTile>>,
+    // Team expected to move next, cookie always starts
+    turn: Team,
+    // Whether moves out of turn are rejected
+    strict: bool,
+    // Every move played so far, in order
+    moves: Vec<Move>,
+}
+
+impl Default for Board {
+    fn default() -> Self {
+        Board::new(BoardSize::default())
+    }
 }
 
 impl Board {
-    pub fn new() -> Board {
+    pub fn new(size: BoardSize) -> Board {
         Board {
-            board: [[BoardTile::Empty; 4]; 4],
+            size,
+            board: vec![vec![BoardTile::Empty; size.width]; size.height],
+            turn: Team::Cookie,
+            strict: false,
+            moves: Vec::new(),
         }
     }
 
-    pub fn new_random(rand: &mut StdRng) -> Board {
-        let mut board = Board {
-            board: [[BoardTile::Empty; 4]; 4],
-        };
-        for i in 0..4 {
-            for j in 0..4 {
-                let r = rand.gen::<bool>();
+    pub fn import(size: BoardSize, strict: bool, notation: &str) -> Result<Board, String> {
+        // Replay moves written like "c1 m2 c2", taking turns if the game is strict
+        let mut board = Board::new(size).with_strict(strict);
+        for token in notation.split_whitespace() {
+            let (team, col) = token.split_at(token.chars().next().map_or(0, char::len_utf8));
+            let team = match team {
+                "c" => Team::Cookie,
+                "m" => Team::Milk,
+                _ => return Err("Invalid move: ".to_string() + token),
+            };
+            let col = col
+                .parse::<usize>()
+                .map_err(|_| "Invalid move: ".to_string() + token)?;
+            // Nothing can be played once the game is decided
+            if board.get_result().is_some() {
+                return Err("Game already over: ".to_string() + token);
+            }
+            board
+                .place_tile(team, col)
+                .map_err(|err| format!("{}: {}", err, token))?;
+        }
+        Ok(board)
+    }
+
+    pub fn export(&self) -> String {
+        self.moves
+            .iter()
+            .map(|m| format!("{}{}", m.team.letter(), m.column))
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+
+    pub fn moves(&self) -> &[Move] {
+        &self.moves
+    }
+
+    pub fn replay(&self, step: usize) -> Option<Board> {
+        // Rebuild the board as it was after the given number of moves
+        let mut board = Board::new(self.size).with_strict(self.strict);
+        for m in self.moves.get(..step)? {
+            board.place_tile(m.team, m.column).ok()?;
+        }
+        Some(board)
+    }
+
+    pub fn undo(&mut self) -> Option<Move> {
+        // Remove the last tile and give the turn back to its team
+        let last = self.moves.pop()?;
+        self.board[self.size.height - last.row][last.column - 1] = BoardTile::Empty;
+        self.turn = last.team;
+        Some(last)
+    }
+
+    pub fn last_move(&self) -> Option<&Move> {
+        self.moves.last()
+    }
+
+    pub fn with_strict(mut self, strict: bool) -> Board {
+        self.strict = strict;
+        self
+    }
+
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    pub fn next_turn(&self) -> Team {
+        self.turn
+    }
+
+    /// Fills a board with random tiles, each a cookie with the given probability or a fair coin flip
+    pub fn new_random(
+        rand: &mut StdRng,
+        size: BoardSize,
+        cookie_probability: Option<f64>,
+    ) -> Board {
+        let mut board = Board::new(size);
+        for row in board.board.iter_mut() {
+            for tile in row.iter_mut() {
+                let r = match cookie_probability {
+                    Some(p) => rand.gen_bool(p),
+                    None => rand.gen::<bool>(),
+                };
                 if r {
-                    board.board[i][j] = BoardTile::Cookie;
+                    *tile = BoardTile::Cookie;
                 } else {
-                    board.board[i][j] = BoardTile::Milk;
+                    *tile = BoardTile::Milk;
                 }
             }
         }
         board
     }
 
-    pub fn place_tile(&mut self, team: String, col: usize) -> Result<(), String> {
-        // Convert from String to BoardTile
-        let tile = match team.as_str() {
-            "cookie" => BoardTile::Cookie,
-            "milk" => BoardTile::Milk,
-            _ => return Err("Invalid team".to_string()),
-        };
+    pub fn size(&self) -> BoardSize {
+        self.size
+    }
+
+    pub fn tiles(&self) -> &[Vec<BoardTile>] {
+        &self.board
+    }
+
+    pub fn place_tile(&mut self, team: Team, col: usize) -> Result<(), PlaceError> {
+        // Only the team whose turn it is may move in strict mode
+        if self.strict && team != self.turn {
+            return Err(PlaceError::NotYourTurn);
+        }
 
         // Check if col is a valid index
-        if col < 1 || col > 4 {
-            return Err("Invalid column".to_string());
+        if !(1..=self.size.width).contains(&col) {
+            return Err(PlaceError::InvalidColumn);
         }
         let col = col - 1;
 
         // Try to find empty space in column
-        for i in (0..4).rev() {
-            if let BoardTile::Empty = self.board[i][col] {
-                // Place tile here
-                self.board[i][col] = tile;
+        for (i, row) in self.board.iter_mut().enumerate().rev() {
+            if row[col].is_empty() {
+                // Place tile here, record it and hand the turn over
+                row[col] = team.tile();
+                self.turn = team.other();
+                self.moves.push(Move {
+                    team,
+                    column: col + 1,
+                    row: self.size.height - i,
+                    timestamp: Utc::now(),
+                });
                 return Ok(());
             }
         }
 
         // Column is full
-        return Err("Column full".to_string());
+        Err(PlaceError::ColumnFull)
     }
 
     pub fn get_result(&self) -> Option<String> {
@@ -90,69 +355,189 @@ impl Board {
         }
 
         // See if board is filled
-        if self.board.iter().flatten().any(|t| t.is_empty()) {
-            None
-        } else {
+        if self.is_full() {
             Some("No winner.".to_string())
+        } else {
+            None
         }
     }
 
-    fn get_winner(&self) -> BoardTile {
-        for i in 0..4 {
-            // Check each column
-            let mut win = true;
-            for j in 0..3 {
-                if self.board[j][i] != self.board[j + 1][i] {
-                    win = false;
+    pub fn is_full(&self) -> bool {
+        !self.board.iter().flatten().any(|t| t.is_empty())
+    }
+
+    /// Every line of win_length tiles in all directions, as (row, column) grid indices
+    pub fn lines(&self) -> Vec<Vec<(usize, usize)>> {
+        let mut lines = Vec::new();
+        for i in 0..self.size.height as isize {
+            for j in 0..self.size.width as isize {
+                for (di, dj, _) in DIRECTIONS {
+                    let line: Option<Vec<(usize, usize)>> = (0..self.size.win_length as isize)
+                        .map(|step| {
+                            let (i, j) = (i + di * step, j + dj * step);
+                            self.tile_at(i, j)?;
+                            Some((i as usize, j as usize))
+                        })
+                        .collect();
+                    lines.extend(line);
                 }
             }
-            if win {
-                return self.board[0][i];
-            }
+        }
+        lines
+    }
 
-            // Check each row
-            if self.board[i].windows(2).all(|w| w[0] == w[1]) {
-                return self.board[i][0];
+    pub fn winning_lines(&self) -> Vec<WinningLine> {
+        // Find the longest runs of one team, starting only where a run begins
+        let mut lines = Vec::new();
+        for (i, row) in self.board.iter().enumerate() {
+            for (j, &tile) in row.iter().enumerate() {
+                let Some(team) = tile.team() else {
+                    continue;
+                };
+                let (i, j) = (i as isize, j as isize);
+                for (di, dj, direction) in DIRECTIONS {
+                    if self.tile_at(i - di, j - dj) == Some(tile) {
+                        continue;
+                    }
+                    let tiles: Vec<Position> = (0..)
+                        .map(|step| (i + di * step, j + dj * step))
+                        .take_while(|(i, j)| self.tile_at(*i, *j) == Some(tile))
+                        .map(|(i, j)| self.position(i as usize, j as usize))
+                        .collect();
+                    if tiles.len() >= self.size.win_length {
+                        lines.push(WinningLine {
+                            team,
+                            direction,
+                            tiles,
+                        });
+                    }
+                }
             }
         }
+        lines
+    }
 
-        // Check diagonals
-        // Top left to bottom right
-        if self.board[0][0] == self.board[1][1]
-            && self.board[1][1] == self.board[2][2]
-            && self.board[2][2] == self.board[3][3]
-        {
-            return self.board[0][0];
-        }
-        // Top right to bottom left
-        if self.board[0][3] == self.board[1][2]
-            && self.board[1][2] == self.board[2][1]
-            && self.board[2][1] == self.board[3][0]
-        {
-            return self.board[0][3];
+    fn position(&self, i: usize, j: usize) -> Position {
+        Position {
+            column: j + 1,
+            row: self.size.height - i,
         }
-
-        BoardTile::Empty
     }
-}
 
-impl ToString for Board {
-    fn to_string(&self) -> String {
+    pub fn render(&self, highlight: bool) -> String {
+        // Optionally mark the tiles of winning lines
+        let winning: Vec<Position> = if highlight {
+            self.winning_lines()
+                .into_iter()
+                .flat_map(|line| line.tiles)
+                .collect()
+        } else {
+            Vec::new()
+        };
+
         // Convert board to string
         let mut str = String::new();
-        for row in self.board {
+        for (i, row) in self.board.iter().enumerate() {
             str.push('⬜');
-            for cell in row {
-                str.push(cell.emoji())
+            for (j, cell) in row.iter().enumerate() {
+                if winning.contains(&self.position(i, j)) {
+                    str.push('🟨');
+                } else {
+                    str.push(cell.emoji());
+                }
             }
             str.push('⬜');
             str.push('\n');
         }
-        str.push_str("⬜⬜⬜⬜⬜⬜\n");
+        str.push_str(&"⬜".repeat(self.size.width + 2));
+        str.push('\n');
         if let Some(res) = self.get_result() {
             str.push_str(&res);
             str.push('\n');
         }
-        str.to_string()
+        str
+    }
+
+    fn tile_at(&self, row: isize, col: isize) -> Option<BoardTile> {
+        let row = self.board.get(usize::try_from(row).ok()?)?;
+        row.get(usize::try_from(col).ok()?).copied()
+    }
+
+    pub fn get_winner(&self) -> BoardTile {
+        // Check every line of win_length tiles starting at each tile
+        for (i, row) in self.board.iter().enumerate() {
+            for (j, &tile) in row.iter().enumerate() {
+                if tile.is_empty() {
+                    continue;
+                }
+                for (di, dj, _) in DIRECTIONS {
+                    let wins = (1..self.size.win_length as isize).all(|step| {
+                        self.tile_at(i as isize + di * step, j as isize + dj * step) == Some(tile)
+                    });
+                    if wins {
+                        return tile;
+                    }
+                }
+            }
+        }
+
+        BoardTile::Empty
+    }
+}
+
+impl fmt::Display for Board {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.render(false))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn board(width: usize, height: usize, win_length: usize, notation: &str) -> Board {
+        let size = BoardSize {
+            width,
+            height,
+            win_length,
+        };
+        Board::import(size, false, notation).unwrap()
+    }
+
+    #[test]
+    fn lines() {
+        // Four rows, four columns and both diagonals
+        assert_eq!(Board::default().lines().len(), 10);
+        assert_eq!(board(7, 6, 4, "").lines().len(), 69);
+    }
+
+    #[test]
+    fn connect_four() {
+        let board = board(7, 6, 4, "c1 m2 c2 m3 m3 c3 m4 m4 m4 c4");
+        assert_eq!(board.get_winner(), BoardTile::Cookie);
+        let lines = board.winning_lines();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].direction, Direction::RisingDiagonal);
+        assert_eq!(lines[0].tiles.len(), 4);
+    }
+
+    #[test]
+    fn no_winner_below_win_length() {
+        let board = board(7, 6, 4, "c1 c1 c1 m2 m3 m4");
+        assert_eq!(board.get_winner(), BoardTile::Empty);
+        assert!(board.winning_lines().is_empty());
+        assert_eq!(board.get_result(), None);
+    }
+
+    #[test]
+    fn long_run_is_one_line() {
+        // Import stops at the first win, so place the fifth tile directly
+        let mut board = board(5, 5, 4, "c1 c2 c3 c4");
+        board.place_tile(Team::Cookie, 5).unwrap();
+        assert_eq!(board.get_winner(), BoardTile::Cookie);
+        let lines = board.winning_lines();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].direction, Direction::Row);
+        assert_eq!(lines[0].tiles.len(), 5);
     }
 }
@@ -0,0 +1,191 @@
+use sqlx::{
+    prelude::FromRow,
+    types::{
+        chrono::{DateTime, Utc},
+        Uuid,
+    },
+    Error, PgPool,
+};
+use tokio::task::JoinHandle;
+
+use super::{board::Team, FactoryState};
+
+const CREATE_FINISHED_GAMES_QUERY: &str = "
+        CREATE TABLE IF NOT EXISTS finished_games (
+            id UUID PRIMARY KEY,
+            winner TEXT,
+            move_count INT NOT NULL,
+            duration_ms BIGINT NOT NULL,
+            cookie_player TEXT,
+            milk_player TEXT,
+            finished_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+        );
+        ";
+
+// Restricts a query to games finished within an optional time window
+const TIME_WINDOW: &str = "($1::timestamptz IS NULL OR finished_at >= $1)
+        AND ($2::timestamptz IS NULL OR finished_at <= $2)";
+
+pub async fn init_db(pool: &PgPool) -> Result<(), Error> {
+    // Create table
+    sqlx::query(CREATE_FINISHED_GAMES_QUERY)
+        .execute(pool)
+        .await
+        .map(|_| ())
+}
+
+#[derive(Debug)]
+pub struct GameRecord {
+    // Winning team, or None for a draw
+    winner: Option<String>,
+    move_count: i32,
+    duration_ms: i64,
+    cookie_player: Option<String>,
+    milk_player: Option<String>,
+}
+
+impl GameRecord {
+    pub fn from_finished(f_state: &FactoryState) -> Option<GameRecord> {
+        // Only games that have been played to the end are recorded
+        let board = &f_state.board;
+        board.get_result()?;
+        let moves = board.moves();
+        let duration = match (moves.first(), moves.last()) {
+            (Some(first), Some(last)) => last.timestamp - first.timestamp,
+            _ => return None,
+        };
+        Some(GameRecord {
+            winner: board.get_winner().team().map(|team| team.to_string()),
+            move_count: moves.len() as i32,
+            duration_ms: duration.num_milliseconds(),
+            cookie_player: f_state.names.get(&Team::Cookie).cloned(),
+            milk_player: f_state.names.get(&Team::Milk).cloned(),
+        })
+    }
+}
+
+/// A finished game being saved, and the row it is saved as
+pub struct Recorded {
+    id: Uuid,
+    insert: JoinHandle<()>,
+}
+
+pub async fn record_game(pool: &PgPool, id: Uuid, record: GameRecord) -> Result<(), Error> {
+    sqlx::query(
+        "INSERT INTO finished_games (id, winner, move_count, duration_ms, cookie_player, milk_player)
+        VALUES ($1, $2, $3, $4, $5, $6)",
+    )
+    .bind(id)
+    .bind(record.winner)
+    .bind(record.move_count)
+    .bind(record.duration_ms)
+    .bind(record.cookie_player)
+    .bind(record.milk_player)
+    .execute(pool)
+    .await
+    .map(|_| ())
+}
+
+pub fn record_if_finished(pool: &PgPool, f_state: &mut FactoryState) {
+    // Each game is saved once, until an undo takes the result back
+    if f_state.recorded.is_some() || f_state.imported {
+        return;
+    }
+
+    // Save in the background so the move is not held up by the database
+    if let Some(record) = GameRecord::from_finished(f_state) {
+        let id = Uuid::new_v4();
+        let pool = pool.clone();
+        let insert = tokio::spawn(async move {
+            if let Err(e) = record_game(&pool, id, record).await {
+                eprintln!("{:?}", e);
+            }
+        });
+        f_state.recorded = Some(Recorded { id, insert });
+    }
+}
+
+pub fn forget_game(pool: &PgPool, f_state: &mut FactoryState) {
+    // A game that is no longer finished should not count in the statistics
+    if let Some(Recorded { id, insert }) = f_state.recorded.take() {
+        let pool = pool.clone();
+        tokio::spawn(async move {
+            // Wait for the insert so the row is not deleted before it exists
+            let _ = insert.await;
+            let deleted = sqlx::query("DELETE FROM finished_games WHERE id = $1")
+                .bind(id)
+                .execute(&pool)
+                .await;
+            if let Err(e) = deleted {
+                eprintln!("{:?}", e);
+            }
+        });
+    }
+}
+
+#[derive(FromRow, Debug)]
+pub struct GameTotals {
+    pub games: i64,
+    pub cookie_wins: i64,
+    pub milk_wins: i64,
+    pub draws: i64,
+    pub average_moves: Option<f64>,
+    pub average_duration_ms: Option<f64>,
+}
+
+pub async fn game_totals(
+    pool: &PgPool,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+) -> Result<GameTotals, Error> {
+    sqlx::query_as::<_, GameTotals>(&format!(
+        "SELECT COUNT(*) AS games,
+            COUNT(*) FILTER (WHERE winner = 'cookie') AS cookie_wins,
+            COUNT(*) FILTER (WHERE winner = 'milk') AS milk_wins,
+            COUNT(*) FILTER (WHERE winner IS NULL) AS draws,
+            AVG(move_count)::float8 AS average_moves,
+            AVG(duration_ms)::float8 AS average_duration_ms
+        FROM finished_games WHERE {TIME_WINDOW}"
+    ))
+    .bind(from)
+    .bind(to)
+    .fetch_one(pool)
+    .await
+}
+
+#[derive(FromRow, Debug)]
+pub struct PlayerTotals {
+    pub player: String,
+    pub games: i64,
+    pub wins: i64,
+    pub losses: i64,
+    pub draws: i64,
+}
+
+pub async fn player_totals(
+    pool: &PgPool,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+) -> Result<Vec<PlayerTotals>, Error> {
+    // Count each game once for each named participant
+    sqlx::query_as::<_, PlayerTotals>(&format!(
+        "SELECT player,
+            COUNT(*) AS games,
+            COUNT(*) FILTER (WHERE winner = team) AS wins,
+            COUNT(*) FILTER (WHERE winner IS NOT NULL AND winner <> team) AS losses,
+            COUNT(*) FILTER (WHERE winner IS NULL) AS draws
+        FROM (
+            SELECT cookie_player AS player, 'cookie' AS team, winner FROM finished_games
+            WHERE cookie_player IS NOT NULL AND {TIME_WINDOW}
+            UNION ALL
+            SELECT milk_player AS player, 'milk' AS team, winner FROM finished_games
+            WHERE milk_player IS NOT NULL AND {TIME_WINDOW}
+        ) AS participants
+        GROUP BY player
+        ORDER BY wins DESC, games ASC, player ASC"
+    ))
+    .bind(from)
+    .bind(to)
+    .fetch_all(pool)
+    .await
+}
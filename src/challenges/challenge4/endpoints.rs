@@ -1,72 +1,171 @@
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
+    extract::{Path, Query, State},
+    http::{
+        header::{ACCEPT, CONTENT_TYPE},
+        HeaderMap, StatusCode,
+    },
+    response::{IntoResponse, Response},
+    Json,
 };
-use rand::{rngs::StdRng, SeedableRng};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::Deserialize;
 
-use super::{board::Board, AppState};
+use super::{
+    board::{Board, BoardSize, PlaceError, Team},
+    db::record_if_finished,
+    games::{check_any_player, check_player, CurrentGame},
+    render::{to_svg, BoardView},
+    AppState, RNG_SEED,
+};
+
+#[derive(Deserialize)]
+pub struct BoardQuery {
+    // Mark winning tiles in the text rendering
+    highlight: Option<bool>,
+}
 
-pub async fn get_board(State(state): State<AppState>) -> (StatusCode, String) {
-    // Return board as a string
+pub async fn get_board(
+    CurrentGame(state): CurrentGame,
+    Query(query): Query<BoardQuery>,
+    headers: HeaderMap,
+) -> Response {
     let f_state = state.read().await;
     let board = &f_state.board;
-    (StatusCode::OK, board.to_string())
+
+    // Render the board in the format the client asked for
+    let accept = headers
+        .get(ACCEPT)
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or_default();
+    if accept.contains("application/json") {
+        Json(BoardView::from(board)).into_response()
+    } else if accept.contains("image/svg+xml") {
+        ([(CONTENT_TYPE, "image/svg+xml")], to_svg(board)).into_response()
+    } else {
+        // Return board as a string
+        let highlight = query.highlight.unwrap_or(false);
+        (StatusCode::OK, board.render(highlight)).into_response()
+    }
 }
 
-pub async fn reset_board(State(state): State<AppState>) -> (StatusCode, String) {
+pub async fn reset_board(
+    CurrentGame(state): CurrentGame,
+    Query(size): Query<BoardSize>,
+    headers: HeaderMap,
+) -> (StatusCode, String) {
+    // Check requested dimensions
+    if let Err(err) = size.validate() {
+        return (StatusCode::BAD_REQUEST, err);
+    }
+
     // Reset board to a new one
     let mut f_state = state.write().await;
 
+    // Only the players of a strict game may start it over
+    if f_state.board.is_strict() && !check_any_player(&headers, &f_state.players) {
+        return (StatusCode::FORBIDDEN, "Invalid player token\n".to_string());
+    }
+
     // Reset RNG
-    let rng = &mut f_state.rng;
-    *rng = StdRng::seed_from_u64(2024);
+    f_state.rng = StdRng::seed_from_u64(RNG_SEED);
 
-    // Reset board
-    let board = &mut f_state.board;
-    *board = Board::new();
-    (StatusCode::OK, board.to_string())
+    // Reset board, keeping the game mode, a new game can be recorded again
+    let strict = f_state.board.is_strict();
+    f_state.board = Board::new(size).with_strict(strict);
+    f_state.recorded = None;
+    f_state.imported = false;
+    f_state.notify();
+    (StatusCode::OK, f_state.board.to_string())
+}
+
+#[derive(Deserialize)]
+pub struct PlacePath {
+    team: Team,
+    col: usize,
 }
 
 pub async fn place_tile(
-    State(state): State<AppState>,
-    Path((team, col)): Path<(String, usize)>,
+    State(app): State<AppState>,
+    CurrentGame(state): CurrentGame,
+    Path(PlacePath { team, col }): Path<PlacePath>,
+    headers: HeaderMap,
 ) -> (StatusCode, String) {
     // Get write access to board
     let mut f_state = state.write().await;
+
+    // Only the player who joined as this team may move for it in strict mode
+    if f_state.board.is_strict() && !check_player(&headers, &f_state.players, team) {
+        return (StatusCode::FORBIDDEN, "Invalid player token\n".to_string());
+    }
     let board = &mut f_state.board;
 
     // Check if board is already complete
-    if let Some(_) = board.get_result() {
+    if board.get_result().is_some() {
         return (StatusCode::SERVICE_UNAVAILABLE, board.to_string());
     }
 
     // Try to place tile
     if let Err(err) = board.place_tile(team, col) {
-        return match err.as_str() {
-            // Return generated error
-            "Invalid team" | "Invalid column" => (StatusCode::BAD_REQUEST, board.to_string()),
-            "Column full" => (StatusCode::SERVICE_UNAVAILABLE, board.to_string()),
-            // Unexpected error
-            _ => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Unexpected error: ".to_string() + &err,
-            ),
-        };
+        return place_error(board, err);
     }
 
-    // Check if board is complete
-    let result = board.get_result();
+    f_state.notify();
+    record_if_finished(&app.pool, &mut f_state);
+    (StatusCode::OK, f_state.board.to_string())
+}
 
-    if let Some(_) = result {
-        (StatusCode::OK, board.to_string())
-    } else {
-        (StatusCode::OK, board.to_string())
+/// Maps a failed move to the response sent back to the player
+pub fn place_error(board: &Board, err: PlaceError) -> (StatusCode, String) {
+    match err {
+        PlaceError::InvalidColumn => (StatusCode::BAD_REQUEST, board.to_string()),
+        PlaceError::ColumnFull => (StatusCode::SERVICE_UNAVAILABLE, board.to_string()),
+        PlaceError::NotYourTurn => (
+            StatusCode::CONFLICT,
+            format!("Not your turn, {} moves next\n", board.next_turn()),
+        ),
     }
 }
 
-pub async fn random_board(State(state): State<AppState>) -> Result<String, ()> {
+#[derive(Deserialize)]
+pub struct RandomQuery {
+    // Seed to reproduce a board, drawn from the game's RNG if missing
+    seed: Option<u64>,
+    // Chance of each tile being a cookie, between 0 and 1
+    cookie_probability: Option<f64>,
+}
+
+pub async fn random_board(
+    CurrentGame(state): CurrentGame,
+    Query(query): Query<RandomQuery>,
+    headers: HeaderMap,
+) -> Response {
+    if query
+        .cookie_probability
+        .is_some_and(|p| !(0.0..=1.0).contains(&p))
+    {
+        return (StatusCode::BAD_REQUEST, "Invalid probability\n").into_response();
+    }
+
     let mut f_state = state.write().await;
-    f_state.board = Board::new_random(&mut f_state.rng);
 
-    Ok(f_state.board.to_string())
+    // Only the players of a strict game may replace its board
+    if f_state.board.is_strict() && !check_any_player(&headers, &f_state.players) {
+        return (StatusCode::FORBIDDEN, "Invalid player token\n").into_response();
+    }
+
+    // Every board comes from its own seed, sent back so it can be reproduced
+    let seed = query.seed.unwrap_or_else(|| f_state.rng.gen());
+    let mut rng = StdRng::seed_from_u64(seed);
+    let size = f_state.board.size();
+    let strict = f_state.board.is_strict();
+    f_state.board = Board::new_random(&mut rng, size, query.cookie_probability).with_strict(strict);
+    f_state.recorded = None;
+    f_state.imported = false;
+    f_state.notify();
+
+    (
+        [("X-Board-Seed", seed.to_string())],
+        f_state.board.to_string(),
+    )
+        .into_response()
 }
@@ -0,0 +1,46 @@
+use std::convert::Infallible;
+
+use axum::response::{
+    sse::{Event, KeepAlive},
+    Sse,
+};
+use serde::Serialize;
+use tokio_stream::{wrappers::BroadcastStream, Stream, StreamExt};
+
+use super::{board::Board, games::CurrentGame};
+
+#[derive(Serialize, Clone, Debug)]
+pub struct BoardUpdate {
+    board: String,
+    result: Option<String>,
+}
+
+impl From<&Board> for BoardUpdate {
+    fn from(board: &Board) -> Self {
+        BoardUpdate {
+            board: board.to_string(),
+            result: board.get_result(),
+        }
+    }
+}
+
+fn to_event(update: &BoardUpdate) -> Event {
+    Event::default()
+        .event("board")
+        .json_data(update)
+        .unwrap_or_default()
+}
+
+pub async fn board_events(
+    CurrentGame(state): CurrentGame,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    // Start with the current board, then follow every change
+    let f_state = state.read().await;
+    let current = to_event(&BoardUpdate::from(&f_state.board));
+    let updates = BroadcastStream::new(f_state.updates.subscribe())
+        // Spectators that fall behind simply skip the missed updates
+        .filter_map(|update| update.ok())
+        .map(|update| Ok(to_event(&update)));
+
+    Sse::new(tokio_stream::once(Ok(current)).chain(updates)).keep_alive(KeepAlive::default())
+}
@@ -0,0 +1,143 @@
+use std::{collections::HashMap, sync::Arc};
+
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Path, Query, State},
+    http::{header::AUTHORIZATION, request::Parts, HeaderMap, StatusCode},
+    Json,
+};
+use rand::{distributions::Alphanumeric, Rng};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::{sync::RwLock, time::Instant};
+
+use super::{
+    ai::TeamPath, board::Team, AppState, FactoryState, GameState, GAME_TTL, SWEEP_INTERVAL,
+};
+
+// Id of the game used by the routes that do not name one
+pub const DEFAULT_GAME: &str = "default";
+
+/// The game named by the `:id` path parameter, or the default game
+pub struct CurrentGame(pub GameState);
+
+#[async_trait]
+impl FromRequestParts<AppState> for CurrentGame {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, StatusCode> {
+        // Get the game id from the path
+        let Path(params) = Path::<HashMap<String, String>>::from_request_parts(parts, state)
+            .await
+            .map_err(|_| StatusCode::BAD_REQUEST)?;
+        let id = params.get("id").map(String::as_str).unwrap_or(DEFAULT_GAME);
+
+        // Look up the game and mark it as active
+        let game = state
+            .games
+            .read()
+            .await
+            .get(id)
+            .cloned()
+            .ok_or(StatusCode::NOT_FOUND)?;
+        game.write().await.last_active = Instant::now();
+        Ok(CurrentGame(game))
+    }
+}
+
+fn random_id() -> String {
+    rand::thread_rng()
+        .sample_iter(Alphanumeric)
+        .take(16)
+        .map(|x| x as char)
+        .collect()
+}
+
+#[derive(Deserialize)]
+pub struct NewGameQuery {
+    // Enforce turns and player tokens
+    strict: Option<bool>,
+}
+
+pub async fn create_game(
+    State(state): State<AppState>,
+    Query(query): Query<NewGameQuery>,
+) -> (StatusCode, Json<Value>) {
+    // Create game id
+    let id = random_id();
+    let f_state = FactoryState::new(query.strict.unwrap_or(false));
+
+    let mut games = state.games.write().await;
+    expire_games(&mut games).await;
+    games.insert(id.clone(), Arc::new(RwLock::new(f_state)));
+
+    (StatusCode::CREATED, Json(json!({ "id": id })))
+}
+
+/// Removes idle games every `SWEEP_INTERVAL`, runs for as long as the server does
+pub async fn sweep_games(games: Arc<RwLock<HashMap<String, GameState>>>) {
+    let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+    loop {
+        interval.tick().await;
+        expire_games(&mut *games.write().await).await;
+    }
+}
+
+async fn expire_games(games: &mut HashMap<String, GameState>) {
+    // Find games that have been idle for too long, never removing the default game
+    let mut expired = Vec::new();
+    for (id, game) in games.iter() {
+        if id != DEFAULT_GAME && game.read().await.last_active.elapsed() > GAME_TTL {
+            expired.push(id.clone());
+        }
+    }
+    for id in expired {
+        games.remove(&id);
+    }
+}
+
+#[derive(Deserialize)]
+pub struct JoinQuery {
+    // Name shown on the leaderboard
+    name: Option<String>,
+}
+
+pub async fn join_game(
+    CurrentGame(state): CurrentGame,
+    Path(TeamPath { team }): Path<TeamPath>,
+    Query(query): Query<JoinQuery>,
+) -> (StatusCode, String) {
+    // Each team can only be joined once
+    let mut f_state = state.write().await;
+    if f_state.players.contains_key(&team) {
+        return (StatusCode::CONFLICT, "Team already joined\n".to_string());
+    }
+
+    // Issue a token that has to be sent with every move for this team
+    let token = random_id();
+    if let Some(name) = query.name {
+        f_state.names.insert(team, name);
+    }
+    f_state.players.insert(team, token.clone());
+    (StatusCode::CREATED, token)
+}
+
+fn bearer(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+}
+
+/// Checks the bearer token sent by a player against the one issued for their team
+pub fn check_player(headers: &HeaderMap, players: &HashMap<Team, String>, team: Team) -> bool {
+    match (players.get(&team), bearer(headers)) {
+        (Some(expected), Some(token)) => expected == token,
+        _ => false,
+    }
+}
+
+/// Checks the bearer token belongs to either player, for changes to the whole game
+pub fn check_any_player(headers: &HeaderMap, players: &HashMap<Team, String>) -> bool {
+    bearer(headers).is_some_and(|token| players.values().any(|expected| expected == token))
+}
@@ -0,0 +1,105 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    board::{Board, BoardSize, Move},
+    db::forget_game,
+    games::{check_any_player, check_player, CurrentGame},
+    AppState,
+};
+
+#[derive(Serialize)]
+pub struct HistoryResponse {
+    moves: Vec<Move>,
+    notation: String,
+}
+
+pub async fn get_history(CurrentGame(state): CurrentGame) -> Json<HistoryResponse> {
+    let f_state = state.read().await;
+    let board = &f_state.board;
+    Json(HistoryResponse {
+        moves: board.moves().to_vec(),
+        notation: board.export(),
+    })
+}
+
+pub async fn undo_move(
+    State(app): State<AppState>,
+    CurrentGame(state): CurrentGame,
+    headers: HeaderMap,
+) -> (StatusCode, String) {
+    let mut f_state = state.write().await;
+
+    // Only the player who made the last move may take it back in strict mode
+    let Some(last) = f_state.board.last_move() else {
+        return (StatusCode::BAD_REQUEST, "Nothing to undo\n".to_string());
+    };
+    if f_state.board.is_strict() && !check_player(&headers, &f_state.players, last.team) {
+        return (StatusCode::FORBIDDEN, "Invalid player token\n".to_string());
+    }
+
+    f_state.board.undo();
+
+    // Only the players of the game may take a recorded result back out of the statistics,
+    // otherwise the game stays recorded and is not recorded again
+    if check_any_player(&headers, &f_state.players) {
+        forget_game(&app.pool, &mut f_state);
+    }
+    f_state.notify();
+    (StatusCode::OK, f_state.board.to_string())
+}
+
+#[derive(Deserialize)]
+pub struct StepPath {
+    step: usize,
+}
+
+pub async fn replay(
+    CurrentGame(state): CurrentGame,
+    Path(StepPath { step }): Path<StepPath>,
+) -> (StatusCode, String) {
+    // Render the board as it was after the given number of moves
+    let f_state = state.read().await;
+    match f_state.board.replay(step) {
+        Some(board) => (StatusCode::OK, board.to_string()),
+        None => (StatusCode::NOT_FOUND, "No such move\n".to_string()),
+    }
+}
+
+pub async fn export(CurrentGame(state): CurrentGame) -> String {
+    state.read().await.board.export()
+}
+
+pub async fn import(
+    CurrentGame(state): CurrentGame,
+    Query(size): Query<BoardSize>,
+    headers: HeaderMap,
+    body: String,
+) -> (StatusCode, String) {
+    // Check requested dimensions
+    if let Err(err) = size.validate() {
+        return (StatusCode::BAD_REQUEST, err);
+    }
+
+    // Rebuild the board from the move list, keeping the game mode
+    let mut f_state = state.write().await;
+
+    // Only the players of a strict game may replace its board
+    if f_state.board.is_strict() && !check_any_player(&headers, &f_state.players) {
+        return (StatusCode::FORBIDDEN, "Invalid player token\n".to_string());
+    }
+    match Board::import(size, f_state.board.is_strict(), &body) {
+        Ok(board) => {
+            f_state.board = board;
+            f_state.recorded = None;
+            f_state.imported = true;
+            f_state.notify();
+            (StatusCode::OK, f_state.board.to_string())
+        }
+        Err(err) => (StatusCode::BAD_REQUEST, err + "\n"),
+    }
+}
@@ -1,37 +1,143 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
+use ai::{ai_move, hint};
 use axum::{
     routing::{get, post},
     Router,
 };
-use board::Board;
+use board::{Board, Team};
+use db::{init_db, Recorded};
 use endpoints::{get_board, place_tile, random_board, reset_board};
+use events::{board_events, BoardUpdate};
+use games::{create_game, join_game, sweep_games, DEFAULT_GAME};
+use history::{export, get_history, import, replay, undo_move};
 use rand::{rngs::StdRng, SeedableRng};
-use tokio::sync::RwLock;
+use sqlx::PgPool;
+use stats::{leaderboard, stats};
+use tokio::{
+    sync::{broadcast, RwLock},
+    time::Instant,
+};
 
+mod ai;
 mod board;
+mod db;
 mod endpoints;
+mod events;
+mod games;
+mod history;
+mod render;
+mod stats;
+
+// Games that have not been used for this long are removed
+const GAME_TTL: Duration = Duration::from_secs(60 * 60);
+
+// How often idle games are looked for
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+// Moves the AI looks ahead by default, and at most
+const DEFAULT_AI_DEPTH: u32 = 4;
+const MAX_AI_DEPTH: u32 = 6;
+
+// Positions the AI may look at per move, so large boards stay responsive
+const MAX_AI_NODES: u64 = 200_000;
+
+// Seed of each game's RNG, set again on reset
+const RNG_SEED: u64 = 2024;
+
+// Updates buffered for each spectator before older ones are skipped
+const UPDATE_CAPACITY: usize = 16;
 
-#[derive(Clone)]
 struct FactoryState {
     board: Board,
-    rng: rand::rngs::StdRng,
+    // Picks the seeds of random boards, so they repeat after every reset
+    rng: StdRng,
+    last_active: Instant,
+    // Player tokens by team name, issued when a player joins
+    players: HashMap<Team, String>,
+    // Player names by team name, if given when joining
+    names: HashMap<Team, String>,
+    // Sends the board to spectators after every change
+    updates: broadcast::Sender<BoardUpdate>,
+    // Set once the finished game has been saved, so it is only saved once
+    recorded: Option<Recorded>,
+    // Imported games were not played here, so they are never recorded
+    imported: bool,
 }
 
-type AppState = Arc<RwLock<FactoryState>>;
+impl FactoryState {
+    fn new(strict: bool) -> FactoryState {
+        FactoryState {
+            board: Board::default().with_strict(strict),
+            rng: StdRng::seed_from_u64(RNG_SEED),
+            last_active: Instant::now(),
+            players: HashMap::new(),
+            names: HashMap::new(),
+            updates: broadcast::channel(UPDATE_CAPACITY).0,
+            recorded: None,
+            imported: false,
+        }
+    }
 
-pub fn router() -> Router {
-    let rng = StdRng::seed_from_u64(2024);
-    let f_state = FactoryState {
-        board: Board::new(),
-        rng,
-    };
-    let state = Arc::new(RwLock::new(f_state));
+    fn notify(&self) {
+        // Nobody may be listening, which is fine
+        let _ = self.updates.send(BoardUpdate::from(&self.board));
+    }
+}
+
+type GameState = Arc<RwLock<FactoryState>>;
 
+#[derive(Clone)]
+struct AppState {
+    games: Arc<RwLock<HashMap<String, GameState>>>,
+    // Finished games are recorded here
+    pool: PgPool,
+}
+
+fn game_router() -> Router<AppState> {
     Router::new()
         .route("/board", get(get_board))
+        .route("/events", get(board_events))
         .route("/reset", post(reset_board))
         .route("/random-board", get(random_board))
         .route("/place/:team/:col", post(place_tile))
+        .route("/join/:team", post(join_game))
+        .route("/ai/:team", post(ai_move))
+        .route("/hint/:team", get(hint))
+        .route("/history", get(get_history))
+        .route("/undo", post(undo_move))
+        .route("/replay/:step", get(replay))
+        .route("/export", get(export))
+        .route("/import", post(import))
+}
+
+pub async fn router(pool: &PgPool) -> Router {
+    // Games can still be played without statistics, so only report failures
+    let pool = pool.clone();
+    if let Err(e) = init_db(&pool).await {
+        eprintln!("{:?}", e);
+        println!("Challenge 12 statistics init failed!");
+    }
+
+    // Legacy routes without a game id use the default game
+    let mut games = HashMap::new();
+    games.insert(
+        DEFAULT_GAME.to_string(),
+        Arc::new(RwLock::new(FactoryState::new(false))),
+    );
+    let state = AppState {
+        games: Arc::new(RwLock::new(games)),
+        pool,
+    };
+
+    // Expire idle games in the background, even when no new ones are created
+    tokio::spawn(sweep_games(state.games.clone()));
+
+    Router::new()
+        .route("/games", post(create_game))
+        .route("/stats", get(stats))
+        .route("/leaderboard", get(leaderboard))
+        .nest("/games/:id", game_router())
+        .merge(game_router())
         .with_state(state)
 }
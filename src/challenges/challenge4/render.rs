@@ -0,0 +1,95 @@
+use serde::Serialize;
+
+use super::board::{Board, BoardTile, Position, Team, WinningLine};
+
+// Size in pixels of a single tile in the SVG rendering
+const TILE_SIZE: usize = 60;
+
+#[derive(Serialize)]
+pub struct BoardView {
+    width: usize,
+    height: usize,
+    win_length: usize,
+    // Rows of tiles, top row first
+    tiles: Vec<Vec<BoardTile>>,
+    result: Option<String>,
+    winner: Option<Team>,
+    winning_lines: Vec<WinningLine>,
+    next_turn: Team,
+}
+
+impl From<&Board> for BoardView {
+    fn from(board: &Board) -> Self {
+        let size = board.size();
+        BoardView {
+            width: size.width,
+            height: size.height,
+            win_length: size.win_length,
+            tiles: board.tiles().to_vec(),
+            result: board.get_result(),
+            winner: board.get_winner().team(),
+            winning_lines: board.winning_lines(),
+            next_turn: board.next_turn(),
+        }
+    }
+}
+
+fn tile_color(tile: BoardTile) -> &'static str {
+    match tile {
+        BoardTile::Cookie => "#c68642",
+        BoardTile::Milk => "#f4f4f4",
+        BoardTile::Empty => "#1e1e1e",
+    }
+}
+
+pub fn to_svg(board: &Board) -> String {
+    let size = board.size();
+    let width = size.width * TILE_SIZE;
+    let height = size.height * TILE_SIZE;
+    let radius = TILE_SIZE * 2 / 5;
+    let winning: Vec<Position> = board
+        .winning_lines()
+        .into_iter()
+        .flat_map(|line| line.tiles)
+        .collect();
+
+    // Draw the frame, then one circle per tile
+    let mut svg = format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{}" viewBox="0 0 {width} {}">"#,
+        height + TILE_SIZE,
+        height + TILE_SIZE,
+    );
+    svg += &format!(r##"<rect width="{width}" height="{height}" fill="#2b5cb8"/>"##);
+    for (i, row) in board.tiles().iter().enumerate() {
+        for (j, tile) in row.iter().enumerate() {
+            // Outline tiles that are part of a winning line
+            let position = Position {
+                column: j + 1,
+                row: size.height - i,
+            };
+            let stroke = if winning.contains(&position) {
+                r##" stroke="#ffd700" stroke-width="4""##
+            } else {
+                ""
+            };
+            svg += &format!(
+                r#"<circle cx="{}" cy="{}" r="{radius}" fill="{}"{stroke}/>"#,
+                j * TILE_SIZE + TILE_SIZE / 2,
+                i * TILE_SIZE + TILE_SIZE / 2,
+                tile_color(*tile),
+            );
+        }
+    }
+
+    // Write the result underneath the board
+    if let Some(result) = board.get_result() {
+        svg += &format!(
+            r#"<text x="{}" y="{}" font-size="{}" text-anchor="middle">{result}</text>"#,
+            width / 2,
+            height + TILE_SIZE * 2 / 3,
+            TILE_SIZE / 2,
+        );
+    }
+    svg += "</svg>";
+    svg
+}
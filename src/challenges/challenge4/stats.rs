@@ -0,0 +1,102 @@
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::types::chrono::{DateTime, Utc};
+
+use super::{
+    db::{game_totals, player_totals},
+    AppState,
+};
+
+#[derive(Deserialize)]
+pub struct WindowQuery {
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+pub struct TeamStats {
+    wins: i64,
+    win_rate: f64,
+}
+
+#[derive(Serialize)]
+pub struct StatsResponse {
+    games: i64,
+    draws: i64,
+    draw_rate: f64,
+    cookie: TeamStats,
+    milk: TeamStats,
+    average_moves: f64,
+    average_duration_ms: f64,
+}
+
+#[derive(Serialize)]
+pub struct PlayerStats {
+    player: String,
+    games: i64,
+    wins: i64,
+    losses: i64,
+    draws: i64,
+    win_rate: f64,
+    draw_rate: f64,
+}
+
+fn rate(count: i64, total: i64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        count as f64 / total as f64
+    }
+}
+
+pub async fn stats(
+    State(state): State<AppState>,
+    Query(window): Query<WindowQuery>,
+) -> Result<Json<StatsResponse>, StatusCode> {
+    let totals = game_totals(&state.pool, window.from, window.to)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(StatsResponse {
+        games: totals.games,
+        draws: totals.draws,
+        draw_rate: rate(totals.draws, totals.games),
+        cookie: TeamStats {
+            wins: totals.cookie_wins,
+            win_rate: rate(totals.cookie_wins, totals.games),
+        },
+        milk: TeamStats {
+            wins: totals.milk_wins,
+            win_rate: rate(totals.milk_wins, totals.games),
+        },
+        average_moves: totals.average_moves.unwrap_or_default(),
+        average_duration_ms: totals.average_duration_ms.unwrap_or_default(),
+    }))
+}
+
+pub async fn leaderboard(
+    State(state): State<AppState>,
+    Query(window): Query<WindowQuery>,
+) -> Result<Json<Vec<PlayerStats>>, StatusCode> {
+    let players = player_totals(&state.pool, window.from, window.to)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let leaderboard = players
+        .into_iter()
+        .map(|p| PlayerStats {
+            win_rate: rate(p.wins, p.games),
+            draw_rate: rate(p.draws, p.games),
+            player: p.player,
+            games: p.games,
+            wins: p.wins,
+            losses: p.losses,
+            draws: p.draws,
+        })
+        .collect();
+    Ok(Json(leaderboard))
+}
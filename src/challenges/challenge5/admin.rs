@@ -0,0 +1,163 @@
+use axum::{
+    extract::{Path, State},
+    http::{header::AUTHORIZATION, HeaderMap, StatusCode},
+    Json,
+};
+use jsonwebtoken::Algorithm;
+use ring::{
+    hmac::{self, HMAC_SHA256},
+    rand::SystemRandom,
+};
+use serde::Deserialize;
+
+use super::{
+    config::GiftConfig,
+    keys::{Key, KeyError, KeyInfo, KeySet},
+    GiftState, GiftStateInner,
+};
+
+pub fn check_admin(config: &GiftConfig, headers: &HeaderMap) -> Result<(), (StatusCode, String)> {
+    // Key management is disabled unless an admin token is configured
+    let Some(expected) = &config.admin_token else {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "Key management disabled\n".to_string(),
+        ));
+    };
+    let token = headers
+        .get(AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "));
+    // Compare fixed-length digests, so timing leaks neither the length nor the content
+    let matches = |token: &str| {
+        hmac::Key::generate(HMAC_SHA256, &SystemRandom::new())
+            .map(|key| {
+                let tag = hmac::sign(&key, expected.as_bytes());
+                hmac::verify(&key, token.as_bytes(), tag.as_ref()).is_ok()
+            })
+            .unwrap_or(false)
+    };
+    match token {
+        Some(token) if matches(token) => Ok(()),
+        _ => Err((
+            StatusCode::UNAUTHORIZED,
+            "Invalid admin token\n".to_string(),
+        )),
+    }
+}
+
+fn key_error(err: KeyError) -> (StatusCode, String) {
+    let status = match err {
+        KeyError::NotFound => StatusCode::NOT_FOUND,
+        KeyError::AlreadyExists | KeyError::Retired | KeyError::Active => StatusCode::CONFLICT,
+    };
+    (status, format!("{}\n", err))
+}
+
+fn store_error(err: sqlx::Error) -> (StatusCode, String) {
+    eprintln!("{:?}", err);
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Key store unavailable\n".to_string(),
+    )
+}
+
+async fn update_keys(
+    state: &GiftStateInner,
+    change: impl FnOnce(&mut KeySet) -> Result<(), KeyError>,
+) -> Result<(), (StatusCode, String)> {
+    // Change a copy and only swap it in once the new statuses are saved
+    let mut keys = state.keys.write().await;
+    let mut updated = keys.clone();
+    change(&mut updated).map_err(key_error)?;
+    state
+        .store
+        .save_statuses(&updated)
+        .await
+        .map_err(store_error)?;
+    *keys = updated;
+    Ok(())
+}
+
+pub async fn list_keys(
+    State(state): State<GiftState>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<KeyInfo>>, (StatusCode, String)> {
+    check_admin(&state.config, &headers)?;
+    Ok(Json(state.keys.read().await.list()))
+}
+
+#[derive(Deserialize)]
+pub struct NewKey {
+    kid: String,
+    // HMAC secret, or an algorithm and PEM private key
+    secret: Option<String>,
+    alg: Option<Algorithm>,
+    pem: Option<String>,
+}
+
+pub async fn add_key(
+    State(state): State<GiftState>,
+    headers: HeaderMap,
+    Json(key): Json<NewKey>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    check_admin(&state.config, &headers)?;
+    if key.kid.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "Missing kid\n".to_string()));
+    }
+    let new_key = match (&key.secret, key.alg, &key.pem) {
+        (Some(secret), None, None) if !secret.is_empty() => Key::hmac(secret),
+        (None, Some(alg), Some(pem)) => Key::from_pem(&key.kid, alg, pem)
+            .map_err(|err| (StatusCode::BAD_REQUEST, format!("Invalid key: {}\n", err)))?,
+        _ => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Expected either a secret or an alg and pem\n".to_string(),
+            ))
+        }
+    };
+
+    // Save the key itself, encrypted, so gifts signed with it still open after a restart
+    let mut keys = state.keys.write().await;
+    let mut updated = keys.clone();
+    let alg = new_key.alg();
+    updated.add(key.kid.clone(), new_key).map_err(key_error)?;
+    state
+        .store
+        .add(
+            &state.cipher,
+            &key.kid,
+            alg,
+            key.secret.as_deref(),
+            key.pem.as_deref(),
+        )
+        .await
+        .map_err(store_error)?;
+    *keys = updated;
+    Ok(StatusCode::CREATED)
+}
+
+#[derive(Deserialize)]
+pub struct KidPath {
+    kid: String,
+}
+
+pub async fn activate_key(
+    State(state): State<GiftState>,
+    Path(KidPath { kid }): Path<KidPath>,
+    headers: HeaderMap,
+) -> Result<StatusCode, (StatusCode, String)> {
+    check_admin(&state.config, &headers)?;
+    update_keys(&state, |keys| keys.activate(&kid)).await?;
+    Ok(StatusCode::OK)
+}
+
+pub async fn retire_key(
+    State(state): State<GiftState>,
+    Path(KidPath { kid }): Path<KidPath>,
+    headers: HeaderMap,
+) -> Result<StatusCode, (StatusCode, String)> {
+    check_admin(&state.config, &headers)?;
+    update_keys(&state, |keys| keys.retire(&kid)).await?;
+    Ok(StatusCode::OK)
+}
@@ -0,0 +1,339 @@
+use std::{env, fs, str::FromStr};
+
+use axum::http::StatusCode;
+use axum_extra::extract::{
+    cookie::{Cookie, SameSite},
+    CookieJar,
+};
+use jsonschema::Validator;
+use serde_json::Value;
+use time::Duration;
+
+// Lifetime of a gift when the request does not ask for one, in seconds
+const DEFAULT_TTL: u64 = 60 * 60 * 24;
+
+// Longest lifetime a request may ask for, in seconds
+const MAX_TTL: u64 = 60 * 60 * 24 * 30;
+
+// Clock skew allowed when checking time claims, in seconds
+const DEFAULT_LEEWAY: u64 = 60;
+
+// Largest cookie value before a gift is split, leaving room for the name and attributes
+const DEFAULT_CHUNK_SIZE: usize = 3800;
+
+// Most cookies one gift may be split across, browsers limit cookies per site
+const MAX_CHUNKS: usize = 20;
+
+// Claims a gift must carry to be unwrapped
+const UNWRAP_REQUIRED_CLAIMS: &str = "exp,nbf,jti";
+
+/// Settings read from the environment, which main fills from the secret store
+pub const CONFIG_VARS: &[&str] = &[
+    "GIFT_DEFAULT_TTL",
+    "GIFT_MAX_TTL",
+    "GIFT_ADMIN_TOKEN",
+    "GIFT_SIGNING_ALG",
+    "GIFT_SIGNING_KEY",
+    "GIFT_SIGNING_KID",
+    "GIFT_TRUSTED_KEYS",
+    "GIFT_ENCRYPTION_KEY",
+    "GIFT_COOKIE_NAME",
+    "GIFT_COOKIE_HOST_PREFIX",
+    "GIFT_COOKIE_SECURE",
+    "GIFT_COOKIE_HTTP_ONLY",
+    "GIFT_COOKIE_SAME_SITE",
+    "GIFT_COOKIE_PATH",
+    "GIFT_COOKIE_DOMAIN",
+    "GIFT_COOKIE_CHUNK_SIZE",
+    "GIFT_ISSUER",
+    "GIFT_AUDIENCE",
+    "GIFT_LEEWAY",
+    "GIFT_UNWRAP_REQUIRED_CLAIMS",
+    "GIFT_DECODE_REQUIRED_CLAIMS",
+    "GIFT_BODY_SCHEMA",
+];
+
+pub struct GiftConfig {
+    pub default_ttl: u64,
+    pub max_ttl: u64,
+    // Bearer token for key management and inspecting gifts, disabled if unset
+    pub admin_token: Option<String>,
+    pub cookie: CookieConfig,
+    // Set on issued gifts and required when unwrapping them
+    pub issuer: Option<String>,
+    pub audience: Option<String>,
+    pub leeway: u64,
+    pub unwrap_claims: Vec<String>,
+    pub decode_claims: Vec<String>,
+    // Schema the body claim has to match
+    pub body_schema: Option<Validator>,
+}
+
+impl GiftConfig {
+    pub fn from_env() -> GiftConfig {
+        let max_ttl = var_or("GIFT_MAX_TTL", MAX_TTL);
+        GiftConfig {
+            default_ttl: var_or("GIFT_DEFAULT_TTL", DEFAULT_TTL).min(max_ttl),
+            max_ttl,
+            admin_token: env::var("GIFT_ADMIN_TOKEN").ok(),
+            cookie: CookieConfig::from_env(),
+            issuer: env::var("GIFT_ISSUER").ok(),
+            audience: env::var("GIFT_AUDIENCE").ok(),
+            leeway: var_or("GIFT_LEEWAY", DEFAULT_LEEWAY),
+            unwrap_claims: claim_list("GIFT_UNWRAP_REQUIRED_CLAIMS", UNWRAP_REQUIRED_CLAIMS),
+            decode_claims: claim_list("GIFT_DECODE_REQUIRED_CLAIMS", ""),
+            body_schema: body_schema(),
+        }
+    }
+}
+
+fn claim_list(name: &str, default: &str) -> Vec<String> {
+    env::var(name)
+        .unwrap_or_else(|_| default.to_string())
+        .split(',')
+        .map(str::trim)
+        .filter(|claim| !claim.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+fn body_schema() -> Option<Validator> {
+    // The schema is either inline JSON or a path to it
+    let schema = env::var("GIFT_BODY_SCHEMA").ok()?;
+    let schema = if schema.trim_start().starts_with('{') {
+        schema
+    } else {
+        fs::read_to_string(&schema)
+            .map_err(|err| println!("Invalid body schema: {}: {}", schema, err))
+            .ok()?
+    };
+    let schema: Value = serde_json::from_str(&schema)
+        .map_err(|err| println!("Invalid body schema: {}", err))
+        .ok()?;
+    jsonschema::validator_for(&schema)
+        .map_err(|err| println!("Invalid body schema: {}", err))
+        .ok()
+}
+
+/// Attributes of the gift cookie, locked down unless configured otherwise
+pub struct CookieConfig {
+    pub name: String,
+    secure: bool,
+    http_only: bool,
+    same_site: SameSite,
+    path: String,
+    domain: Option<String>,
+    chunk_size: usize,
+}
+
+impl CookieConfig {
+    fn from_env() -> CookieConfig {
+        let mut config = CookieConfig {
+            name: env::var("GIFT_COOKIE_NAME").unwrap_or_else(|_| "gift".to_string()),
+            secure: var_or("GIFT_COOKIE_SECURE", true),
+            http_only: var_or("GIFT_COOKIE_HTTP_ONLY", true),
+            same_site: match env::var("GIFT_COOKIE_SAME_SITE").as_deref() {
+                Ok("lax") | Ok("Lax") => SameSite::Lax,
+                Ok("none") | Ok("None") => SameSite::None,
+                _ => SameSite::Strict,
+            },
+            path: env::var("GIFT_COOKIE_PATH").unwrap_or_else(|_| "/".to_string()),
+            domain: env::var("GIFT_COOKIE_DOMAIN").ok(),
+            chunk_size: var_or("GIFT_COOKIE_CHUNK_SIZE", DEFAULT_CHUNK_SIZE).max(1),
+        };
+
+        // Browsers reject SameSite=None cookies that are not Secure
+        if config.same_site == SameSite::None && !config.secure {
+            println!("SameSite=None requires Secure, enabling it");
+            config.secure = true;
+        }
+
+        // __Host- cookies must be Secure, on / and without a domain
+        if var_or("GIFT_COOKIE_HOST_PREFIX", false) {
+            if !config.secure || config.path != "/" || config.domain.is_some() {
+                println!("__Host- prefix requires Secure, Path=/ and no Domain, overriding");
+            }
+            config.name = format!("__Host-{}", config.name);
+            config.secure = true;
+            config.path = "/".to_string();
+            config.domain = None;
+        }
+        config
+    }
+
+    /// Builds the gift cookie, expiring together with the token inside it
+    pub fn build(&self, value: String, max_age: u64) -> Cookie<'static> {
+        let mut cookie = Cookie::build((self.name.clone(), value))
+            .secure(self.secure)
+            .http_only(self.http_only)
+            .same_site(self.same_site)
+            .path(self.path.clone())
+            .max_age(Duration::seconds(max_age as i64));
+        if let Some(domain) = &self.domain {
+            cookie = cookie.domain(domain.clone());
+        }
+        cookie.build()
+    }
+
+    /// Builds the gift cookies, splitting large gifts into `name.0`, `name.1`, ...
+    ///
+    /// The first chunk starts with the number of chunks, as in `3~eyJ...`
+    pub fn chunks(
+        &self,
+        value: String,
+        max_age: u64,
+    ) -> Result<Vec<Cookie<'static>>, (StatusCode, String)> {
+        if value.len() <= self.chunk_size {
+            return Ok(vec![self.build(value, max_age)]);
+        }
+        let count = value.len().div_ceil(self.chunk_size);
+        if count > MAX_CHUNKS {
+            return Err((
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!(
+                    "Gift needs {} cookies, at most {} allowed\n",
+                    count, MAX_CHUNKS
+                ),
+            ));
+        }
+
+        // Tokens are ASCII, so splitting by bytes keeps every chunk valid
+        let mut cookies: Vec<Cookie<'static>> = value
+            .as_bytes()
+            .chunks(self.chunk_size)
+            .enumerate()
+            .map(|(index, chunk)| {
+                let chunk = String::from_utf8_lossy(chunk);
+                let chunk = match index {
+                    0 => format!("{}~{}", count, chunk),
+                    _ => chunk.into_owned(),
+                };
+                let mut cookie = self.build(chunk, max_age);
+                cookie.set_name(format!("{}.{}", self.name, index));
+                cookie
+            })
+            .collect();
+
+        // Clear an earlier unchunked gift, it would be read first
+        cookies.push(self.build(String::new(), 0));
+        Ok(cookies)
+    }
+
+    /// Reads the gift from its cookie, or reassembles it from its chunks
+    pub fn read(&self, jar: &CookieJar) -> Result<String, (StatusCode, String)> {
+        if let Some(cookie) = jar.get(&self.name).filter(|c| !c.value().is_empty()) {
+            return Ok(cookie.value().to_string());
+        }
+        let chunk = |index: usize| jar.get(&format!("{}.{}", self.name, index));
+        let first = chunk(0).ok_or((StatusCode::BAD_REQUEST, "Missing gift\n".to_string()))?;
+        let (count, mut value) = first
+            .value()
+            .split_once('~')
+            .and_then(|(count, value)| Some((count.parse::<usize>().ok()?, value.to_string())))
+            .filter(|(count, _)| (1..=MAX_CHUNKS).contains(count))
+            .ok_or((
+                StatusCode::BAD_REQUEST,
+                "Invalid gift chunk count\n".to_string(),
+            ))?;
+        for index in 1..count {
+            let cookie = chunk(index).ok_or((
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "Missing gift chunk {}.{}, expected {} chunks\n",
+                    self.name, index, count
+                ),
+            ))?;
+            value.push_str(cookie.value());
+        }
+        Ok(value)
+    }
+}
+
+fn var_or<T: FromStr>(name: &str, default: T) -> T {
+    // Fall back to the default when the variable is missing or malformed
+    match env::var(name).map(|v| v.parse()) {
+        Ok(Ok(value)) => value,
+        Ok(Err(_)) => {
+            println!("Invalid value for {}, using default", name);
+            default
+        }
+        Err(_) => default,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(chunk_size: usize) -> CookieConfig {
+        CookieConfig {
+            name: "gift".to_string(),
+            secure: true,
+            http_only: true,
+            same_site: SameSite::Strict,
+            path: "/".to_string(),
+            domain: None,
+            chunk_size,
+        }
+    }
+
+    fn jar(cookies: Vec<Cookie<'static>>) -> CookieJar {
+        cookies
+            .into_iter()
+            .fold(CookieJar::new(), |jar, cookie| jar.add(cookie))
+    }
+
+    #[test]
+    fn single_cookie() {
+        let config = config(10);
+        let cookies = config.chunks("a.b.c".to_string(), 60).unwrap();
+        assert_eq!(cookies.len(), 1);
+        assert_eq!(cookies[0].name(), "gift");
+        assert_eq!(config.read(&jar(cookies)).unwrap(), "a.b.c");
+    }
+
+    #[test]
+    fn chunked_round_trip() {
+        let config = config(4);
+        let value = "header.payload.signature".to_string();
+        let cookies = config.chunks(value.clone(), 60).unwrap();
+        // Six chunks and the cookie clearing an earlier unchunked gift
+        assert_eq!(cookies.len(), 7);
+        assert_eq!(cookies[0].name(), "gift.0");
+        assert_eq!(cookies[0].value(), "6~head");
+        assert_eq!(cookies[6].name(), "gift");
+        assert_eq!(cookies[6].value(), "");
+        assert_eq!(config.read(&jar(cookies)).unwrap(), value);
+    }
+
+    #[test]
+    fn too_many_chunks() {
+        let value = "x".repeat(MAX_CHUNKS + 1);
+        let err = config(1).chunks(value, 60).unwrap_err();
+        assert_eq!(err.0, StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[test]
+    fn missing_chunk() {
+        let config = config(4);
+        let mut cookies = config.chunks("header.payload".to_string(), 60).unwrap();
+        cookies.remove(2);
+        let err = config.read(&jar(cookies)).unwrap_err();
+        assert_eq!(err.0, StatusCode::BAD_REQUEST);
+        assert!(err.1.contains("gift.2"));
+        assert!(config.read(&CookieJar::new()).is_err());
+    }
+
+    #[test]
+    fn bad_chunk_count() {
+        let config = config(4);
+        for first in ["head", "x~head", "0~head", "21~head", "-1~head", "~head"] {
+            let chunks = jar(vec![
+                Cookie::new("gift.0", first.to_string()),
+                Cookie::new("gift.1", "er"),
+            ]);
+            let err = config.read(&chunks).unwrap_err();
+            assert_eq!(err.1, "Invalid gift chunk count\n", "{first}");
+        }
+    }
+}
@@ -1,93 +1,373 @@
-use std::{collections::HashSet, env};
+use std::collections::HashSet;
 
-use axum::http::StatusCode;
-use axum::Json;
-use axum_extra::extract::{cookie::Cookie, CookieJar};
-use jsonwebtoken::{decode, decode_header, encode, DecodingKey, EncodingKey, Header, Validation};
+use axum::{
+    body::Bytes,
+    extract::{Query, State},
+    http::{
+        header::{AUTHORIZATION, CONTENT_TYPE},
+        HeaderMap, StatusCode,
+    },
+    Json,
+};
+use axum_extra::extract::CookieJar;
+use jsonwebtoken::{
+    decode, decode_header, encode,
+    errors::{Error, ErrorKind},
+    get_current_timestamp,
+    jwk::JwkSet,
+    Algorithm, Header, Validation,
+};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use sqlx::types::Uuid;
+
+use super::{
+    config::GiftConfig,
+    jwe::{is_jwe, JweError},
+    payload::Payload,
+    trusted::KeyLookupError,
+    GiftState, GiftStateInner,
+};
 
 #[derive(Serialize, Deserialize)]
-struct Claim {
-    exp: i64,
-    body: Value,
+pub struct Claim {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aud: Option<String>,
+    pub jti: String,
+    pub iat: u64,
+    pub nbf: u64,
+    pub exp: u64,
+    pub body: Value,
+    // Content type of text and binary bodies, JSON bodies have none
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ct: Option<String>,
+    // Compression of the body, as in JWE
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub zip: Option<String>,
+}
+
+impl Claim {
+    pub fn payload(&self) -> Result<Payload, String> {
+        Payload::decode(&self.body, self.ct.as_deref(), self.zip.as_deref())
+    }
+}
+
+#[derive(Deserialize, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum GiftFormat {
+    // Signed JWT, readable by anyone holding the cookie
+    #[default]
+    Signed,
+    // Signed JWT inside a JWE, only readable by unwrapping
+    Encrypted,
 }
 
-pub async fn wrap(Json(body): Json<Value>) -> Result<(StatusCode, CookieJar), StatusCode> {
-    // Get secret from .env
-    let secret = env::var("JWT_SECRET");
-    if secret.is_err() {
-        println!("{:?}", secret.err());
-        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+#[derive(Deserialize)]
+pub struct WrapQuery {
+    // Seconds until the gift expires
+    ttl: Option<u64>,
+    #[serde(default)]
+    format: GiftFormat,
+    // DEFLATE the body before signing
+    #[serde(default)]
+    compress: bool,
+}
+
+pub async fn wrap(
+    State(state): State<GiftState>,
+    Query(query): Query<WrapQuery>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<(StatusCode, CookieJar), (StatusCode, String)> {
+    // Take the TTL from the query or the X-Gift-TTL header
+    let ttl = match query.ttl {
+        Some(ttl) => Some(ttl),
+        None => match headers
+            .get("X-Gift-TTL")
+            .map(|h| h.to_str().map(str::parse))
+        {
+            Some(Ok(Ok(ttl))) => Some(ttl),
+            Some(_) => return Err((StatusCode::BAD_REQUEST, "Invalid TTL\n".to_string())),
+            None => None,
+        },
+    };
+    let config = &state.config;
+    let ttl = ttl.unwrap_or(config.default_ttl);
+    if ttl == 0 || ttl > config.max_ttl {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("TTL must be between 1 and {} seconds\n", config.max_ttl),
+        ));
+    }
+
+    if query.format == GiftFormat::Encrypted && !state.cipher.is_enabled() {
+        return Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Encryption not configured\n".to_string(),
+        ));
     }
-    let secret = secret.unwrap();
+
+    // The content type decides whether the gift is JSON, text or binary
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .and_then(|h| h.to_str().ok())
+        .ok_or((
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "Missing Content-Type\n".to_string(),
+        ))?;
+    let payload = Payload::from_request(content_type, body)
+        .map_err(|err| (StatusCode::BAD_REQUEST, format!("{}\n", err)))?;
+    check_body(config, &payload)?;
+    let encoded = payload.encode(query.compress).map_err(|err| {
+        println!("{}", err);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Compression failed\n".to_string(),
+        )
+    })?;
 
     // Create claim
+    let now = get_current_timestamp();
     let claim = Claim {
-        exp: 10000000000,
-        body,
+        iss: config.issuer.clone(),
+        aud: config.audience.clone(),
+        jti: Uuid::new_v4().to_string(),
+        iat: now,
+        nbf: now,
+        exp: now + ttl,
+        body: encoded.body,
+        ct: encoded.ct,
+        zip: encoded.zip,
+    };
+
+    // Create and sign JWT with the active key, naming it in the header
+    let (kid, alg, key) = state.keys.read().await.signing_key().ok_or((
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "No active key\n".to_string(),
+    ))?;
+    let header = Header {
+        kid: Some(kid),
+        ..Header::new(alg)
+    };
+    let jwt = encode(&header, &claim, &key).map_err(|err| {
+        println!("{:?}", err);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Signing failed\n".to_string(),
+        )
+    })?;
+
+    // Hide the gift if asked to
+    let jwt = match query.format {
+        GiftFormat::Signed => jwt,
+        GiftFormat::Encrypted => state.cipher.encrypt(&jwt).ok_or((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Encryption failed\n".to_string(),
+        ))?,
     };
 
-    // Create and sign JWT with secret
-    let key = EncodingKey::from_secret(secret.as_bytes());
-    let jwt = encode(&Header::default(), &claim, &key);
-    if jwt.is_err() {
-        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    // Set Cookie, split across several if too large, and return
+    let jar = config
+        .cookie
+        .chunks(jwt, ttl)?
+        .into_iter()
+        .fold(CookieJar::new(), CookieJar::add);
+    Ok((StatusCode::OK, jar))
+}
+
+fn unwrap_error(err: &Error) -> (StatusCode, String) {
+    // Tell the client why the gift could not be opened
+    match err.kind() {
+        ErrorKind::ExpiredSignature => (StatusCode::GONE, "Gift expired\n".to_string()),
+        ErrorKind::ImmatureSignature => (StatusCode::FORBIDDEN, "Gift not yet valid\n".to_string()),
+        ErrorKind::InvalidSignature => {
+            (StatusCode::UNAUTHORIZED, "Gift tampered with\n".to_string())
+        }
+        ErrorKind::InvalidIssuer => (StatusCode::UNAUTHORIZED, "Unknown issuer\n".to_string()),
+        ErrorKind::InvalidAudience => (StatusCode::UNAUTHORIZED, "Wrong audience\n".to_string()),
+        ErrorKind::MissingRequiredClaim(claim) => (
+            StatusCode::BAD_REQUEST,
+            format!("Missing claim: {}\n", claim),
+        ),
+        _ => (StatusCode::BAD_REQUEST, "Invalid gift\n".to_string()),
     }
-    let jwt = jwt.unwrap();
+}
 
-    // Set Cookie and return
-    let cookie = Cookie::new("gift", jwt);
-    let jar = CookieJar::new().add(cookie);
-    return Ok((StatusCode::OK, jar));
+/// Checks every claim in `required` is present, not only the registered ones
+pub fn check_required(claims: &Value, required: &[String]) -> Result<(), Error> {
+    match required
+        .iter()
+        .find(|claim| claims.get(claim.as_str()).is_none())
+    {
+        Some(claim) => Err(ErrorKind::MissingRequiredClaim(claim.clone()).into()),
+        None => Ok(()),
+    }
 }
 
-pub async fn unwrap(jar: CookieJar) -> Result<Json<Value>, StatusCode> {
-    // Get cookie from request
-    let cookie = jar.get("gift");
-    if cookie.is_none() {
-        return Err(StatusCode::BAD_REQUEST);
+pub fn check_body(config: &GiftConfig, payload: &Payload) -> Result<(), (StatusCode, String)> {
+    // Gifts are checked against the schema both when wrapped and unwrapped
+    let Some(schema) = &config.body_schema else {
+        return Ok(());
+    };
+    let Payload::Json(body) = payload else {
+        return Err((
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "Gift schema requires a JSON body\n".to_string(),
+        ));
+    };
+    let errors: Vec<String> = schema
+        .iter_errors(body)
+        .map(|err| match err.instance_path.to_string() {
+            path if path.is_empty() => format!("/: {}", err),
+            path => format!("{}: {}", path, err),
+        })
+        .collect();
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("Gift does not match schema\n{}\n", errors.join("\n")),
+        ))
     }
-    let cookie = cookie.unwrap().value();
+}
 
-    // Get secret from .env
-    let secret = env::var("JWT_SECRET");
-    if secret.is_err() {
-        println!("{:?}", secret.err());
-        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+/// Rules every gift has to pass before it is opened
+pub fn gift_validation(config: &GiftConfig, alg: Algorithm) -> Validation {
+    let mut validation = Validation::new(alg);
+    validation.validate_nbf = true;
+    validation.leeway = config.leeway;
+    validation.set_required_spec_claims(&config.unwrap_claims);
+    match &config.issuer {
+        Some(issuer) => validation.set_issuer(&[issuer]),
+        None => validation.iss = None,
+    }
+    match &config.audience {
+        Some(audience) => validation.set_audience(&[audience]),
+        None => validation.validate_aud = false,
     }
-    let secret = secret.unwrap();
-    let key = DecodingKey::from_secret(secret.as_bytes());
+    validation
+}
+
+/// Decrypts and verifies a gift, without checking whether it was revoked
+pub async fn open_gift(
+    state: &GiftStateInner,
+    cookie: &str,
+) -> Result<(Claim, Payload), (StatusCode, String)> {
+    // Encrypted gifts hold a signed one inside
+    let decrypted;
+    let cookie = if is_jwe(cookie) {
+        decrypted = state.cipher.decrypt(cookie).map_err(|err| match err {
+            JweError::DecryptionFailed => {
+                (StatusCode::UNAUTHORIZED, "Gift tampered with\n".to_string())
+            }
+            JweError::Malformed | JweError::UnsupportedAlgorithm => {
+                (StatusCode::BAD_REQUEST, format!("Invalid gift: {}\n", err))
+            }
+        })?;
+        decrypted.as_str()
+    } else {
+        cookie
+    };
+
+    // Verify with whichever key the header names
+    let header = decode_header(cookie).map_err(|err| unwrap_error(&err))?;
+    let (alg, key) = state
+        .keys
+        .read()
+        .await
+        .decoding_key(header.kid.as_deref())
+        .ok_or((StatusCode::UNAUTHORIZED, "Unknown key\n".to_string()))?;
+
+    // Check the signature, time claims, issuer and audience, then the remaining claims
+    let config = &state.config;
+    let claims = decode::<Value>(cookie, &key, &gift_validation(config, alg))
+        .and_then(|data| {
+            check_required(&data.claims, &config.unwrap_claims)?;
+            Ok(data.claims)
+        })
+        .map_err(|err| unwrap_error(&err))?;
+    let claim: Claim = serde_json::from_value(claims)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "Invalid gift\n".to_string()))?;
+    let payload = claim
+        .payload()
+        .map_err(|err| (StatusCode::BAD_REQUEST, format!("Invalid gift: {}\n", err)))?;
+    check_body(config, &payload)?;
+    Ok((claim, payload))
+}
 
-    let claim = decode::<Claim>(cookie, &key, &Validation::default());
-    if claim.is_err() {
-        println!("{:?}", claim.err());
-        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+pub async fn unwrap(
+    State(state): State<GiftState>,
+    headers: HeaderMap,
+    jar: CookieJar,
+) -> Result<Payload, (StatusCode, String)> {
+    // Non-browser clients can send the gift as a bearer token instead of a cookie
+    let bearer = headers
+        .get(AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "));
+    let token = match bearer {
+        Some(token) => token.to_string(),
+        None => state.config.cookie.read(&jar)?,
+    };
+
+    let (claim, payload) = open_gift(&state, &token).await?;
+    let revoked = state
+        .revocations
+        .is_revoked(&claim.jti)
+        .await
+        .map_err(|err| {
+            eprintln!("{:?}", err);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Revocation check failed\n".to_string(),
+            )
+        })?;
+    if revoked {
+        return Err((StatusCode::UNAUTHORIZED, "Gift revoked\n".to_string()));
     }
-    let body = claim.unwrap().claims.body;
-    Ok(Json(body))
+    Ok(payload)
 }
 
-pub async fn decode_jwt(body: String) -> Result<Json<Value>, StatusCode> {
-    // Create RSA public key
-    let key_bytes = include_bytes!("key.pem");
-    let key =
-        DecodingKey::from_rsa_pem(key_bytes).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+pub async fn jwks(State(state): State<GiftState>) -> Json<JwkSet> {
+    // Only public keys are published, HMAC secrets never leave the server
+    Json(state.keys.read().await.jwks())
+}
 
+pub async fn decode_jwt(
+    State(state): State<GiftState>,
+    body: String,
+) -> Result<Json<Value>, StatusCode> {
     // Decode Header
     let header = decode_header(&body).map_err(|_| StatusCode::BAD_REQUEST)?;
 
+    // Only keys trusted for the header's algorithm are tried
+    let keys = state
+        .trusted
+        .candidates(header.kid.as_deref(), header.alg)
+        .map_err(|err| match err {
+            KeyLookupError::UnknownKid => StatusCode::UNAUTHORIZED,
+            KeyLookupError::AlgorithmNotAllowed => StatusCode::BAD_REQUEST,
+        })?;
+
     // Decode JWT
     let mut validation = Validation::new(header.alg);
     validation.validate_exp = false;
+    validation.leeway = state.config.leeway;
     validation.required_spec_claims = HashSet::new();
-    let decrypted_body =
-        decode::<Value>(&body, &key, &validation).map_err(|err| match err.kind() {
-            jsonwebtoken::errors::ErrorKind::InvalidSignature => StatusCode::UNAUTHORIZED,
-            _ => StatusCode::BAD_REQUEST,
-        })?;
-
-    let response = Json(decrypted_body.claims);
-
-    return Ok(response);
+    let mut status = StatusCode::UNAUTHORIZED;
+    for trusted in keys {
+        match decode::<Value>(&body, &trusted.key, &validation) {
+            Ok(decrypted_body) => {
+                return check_required(&decrypted_body.claims, &state.config.decode_claims)
+                    .map(|_| Json(decrypted_body.claims))
+                    .map_err(|_| StatusCode::BAD_REQUEST)
+            }
+            Err(err) if *err.kind() == ErrorKind::InvalidSignature => {}
+            Err(_) => status = StatusCode::BAD_REQUEST,
+        }
+    }
+    Err(status)
 }
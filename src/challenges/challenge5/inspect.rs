@@ -0,0 +1,222 @@
+use std::collections::HashSet;
+
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{
+    decode, decode_header, get_current_timestamp, Algorithm, DecodingKey, Header, Validation,
+};
+use serde::Serialize;
+use serde_json::Value;
+
+use super::{
+    admin::check_admin,
+    endpoints::{check_body, check_required, gift_validation, Claim},
+    jwe::is_jwe,
+    GiftState,
+};
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+enum KeySource {
+    // The keys gifts are wrapped with
+    Gift,
+    // The keys /16/decode trusts
+    Trusted,
+}
+
+#[derive(Serialize)]
+struct TimeChecks {
+    now: u64,
+    leeway: u64,
+    iat: Option<u64>,
+    nbf: Option<u64>,
+    exp: Option<u64>,
+    expired: bool,
+    not_yet_valid: bool,
+}
+
+#[derive(Serialize, Default)]
+struct Verification {
+    // Whether /16/unwrap would open the gift
+    valid: bool,
+    algorithm: Option<Algorithm>,
+    key_source: Option<KeySource>,
+    kid: Option<String>,
+    signature_valid: bool,
+    time: Option<TimeChecks>,
+    revoked: Option<bool>,
+    // First reason the token fails, named after the underlying error kind
+    error: Option<String>,
+}
+
+#[derive(Serialize, Default)]
+pub struct Report {
+    // Outer header of an encrypted gift
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encryption: Option<Value>,
+    header: Option<Header>,
+    claims: Option<Value>,
+    verification: Verification,
+}
+
+fn unverified_claims(token: &str) -> Option<Value> {
+    // Read the claims without checking anything, they are only for display
+    let mut validation = Validation::default();
+    validation.insecure_disable_signature_validation();
+    validation.validate_exp = false;
+    validation.validate_aud = false;
+    validation.required_spec_claims = HashSet::new();
+    decode::<Value>(token, &DecodingKey::from_secret(&[]), &validation)
+        .ok()
+        .map(|data| data.claims)
+}
+
+fn signature_only(alg: Algorithm) -> Validation {
+    let mut validation = Validation::new(alg);
+    validation.validate_exp = false;
+    validation.validate_aud = false;
+    validation.required_spec_claims = HashSet::new();
+    validation
+}
+
+pub async fn inspect(
+    State(state): State<GiftState>,
+    headers: HeaderMap,
+    body: String,
+) -> Result<Json<Report>, (StatusCode, String)> {
+    // Decrypted gift contents are only shown to support staff
+    check_admin(&state.config, &headers)?;
+    let mut report = Report::default();
+    let token = body.trim();
+
+    // Look inside encrypted gifts first
+    let decrypted;
+    let token = if is_jwe(token) {
+        report.encryption = token
+            .split('.')
+            .next()
+            .and_then(|part| URL_SAFE_NO_PAD.decode(part).ok())
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok());
+        match state.cipher.decrypt(token) {
+            Ok(inner) => {
+                decrypted = inner;
+                decrypted.as_str()
+            }
+            Err(err) => {
+                report.verification.error = Some(format!("{:?}", err));
+                return Ok(Json(report));
+            }
+        }
+    } else {
+        token
+    };
+
+    let header = match decode_header(token) {
+        Ok(header) => header,
+        Err(err) => {
+            report.verification.error = Some(format!("{:?}", err.kind()));
+            return Ok(Json(report));
+        }
+    };
+    report.claims = unverified_claims(token);
+    report.header = Some(header.clone());
+    let verification = &mut report.verification;
+    verification.kid = header.kid.clone();
+
+    // Time claims as unwrap would judge them
+    let leeway = state.config.leeway;
+    let now = get_current_timestamp();
+    let claim = |name: &str| {
+        report
+            .claims
+            .as_ref()
+            .and_then(|claims| claims.get(name))
+            .and_then(Value::as_u64)
+    };
+    let (nbf, exp) = (claim("nbf"), claim("exp"));
+    verification.time = Some(TimeChecks {
+        now,
+        leeway,
+        iat: claim("iat"),
+        nbf,
+        exp,
+        expired: exp.is_some_and(|exp| exp + leeway < now),
+        not_yet_valid: nbf.is_some_and(|nbf| nbf > now + leeway),
+    });
+
+    // Prefer the gift key the header names, then the keys trusted by /16/decode
+    // A gift key only applies if it uses the algorithm the token was signed with
+    let gift_key = state
+        .keys
+        .read()
+        .await
+        .decoding_key(header.kid.as_deref())
+        .filter(|(alg, _)| *alg == header.alg);
+    if let Some((alg, key)) = gift_key {
+        verification.key_source = Some(KeySource::Gift);
+        verification.algorithm = Some(alg);
+        verification.signature_valid = decode::<Value>(token, &key, &signature_only(alg)).is_ok();
+
+        // Run the same checks as unwrap to find the exact failure
+        let config = &state.config;
+        let checked =
+            decode::<Value>(token, &key, &gift_validation(config, alg)).and_then(|data| {
+                check_required(&data.claims, &config.unwrap_claims)?;
+                Ok(serde_json::from_value::<Claim>(data.claims)?)
+            });
+        match checked {
+            Ok(claim) => match state.revocations.is_revoked(&claim.jti).await {
+                Ok(revoked) => {
+                    verification.revoked = Some(revoked);
+                    if revoked {
+                        verification.error = Some("Revoked".to_string());
+                    } else {
+                        // Unwrap also rejects bodies it can not decode or that break the schema
+                        verification.error = match claim.payload() {
+                            Err(_) => Some("Payload".to_string()),
+                            Ok(payload) if check_body(config, &payload).is_err() => {
+                                Some("BodySchema".to_string())
+                            }
+                            Ok(_) => None,
+                        };
+                        verification.valid = verification.error.is_none();
+                    }
+                }
+                Err(err) => verification.error = Some(format!("{:?}", err)),
+            },
+            Err(err) => verification.error = Some(format!("{:?}", err.kind())),
+        }
+        return Ok(Json(report));
+    }
+
+    match state.trusted.candidates(header.kid.as_deref(), header.alg) {
+        Ok(keys) => {
+            verification.key_source = Some(KeySource::Trusted);
+            verification.algorithm = Some(header.alg);
+            let validation = signature_only(header.alg);
+            let mut error = None;
+            for trusted in keys {
+                match decode::<Value>(token, &trusted.key, &validation) {
+                    Ok(data) => {
+                        // Same claims /16/decode requires
+                        verification.kid = trusted.kid.clone();
+                        verification.signature_valid = true;
+                        error = check_required(&data.claims, &state.config.decode_claims)
+                            .err()
+                            .map(|err| format!("{:?}", err.kind()));
+                        break;
+                    }
+                    Err(err) => error = Some(format!("{:?}", err.kind())),
+                }
+            }
+            verification.valid = error.is_none();
+            verification.error = error;
+        }
+        Err(err) => verification.error = Some(format!("{:?}", err)),
+    }
+    Ok(Json(report))
+}
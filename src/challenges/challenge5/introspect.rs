@@ -0,0 +1,84 @@
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    Form, Json,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{admin::check_admin, endpoints::open_gift, GiftState};
+
+// A token_type_hint may also be sent, but every token here is a gift
+#[derive(Deserialize)]
+pub struct TokenForm {
+    token: String,
+}
+
+/// Introspection response as described in RFC 7662
+#[derive(Serialize, Default)]
+pub struct Introspection {
+    active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token_type: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    jti: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    iat: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    nbf: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exp: Option<u64>,
+}
+
+fn db_error(err: sqlx::Error) -> (StatusCode, String) {
+    eprintln!("{:?}", err);
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Revocation list unavailable\n".to_string(),
+    )
+}
+
+pub async fn revoke(
+    State(state): State<GiftState>,
+    Form(form): Form<TokenForm>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    // Holding a gift is enough to revoke it, invalid gifts are ignored as in RFC 7009
+    if let Ok((claim, _)) = open_gift(&state, &form.token).await {
+        state
+            .revocations
+            .revoke(&claim.jti, claim.exp)
+            .await
+            .map_err(db_error)?;
+    }
+    Ok(StatusCode::OK)
+}
+
+pub async fn introspect(
+    State(state): State<GiftState>,
+    headers: HeaderMap,
+    Form(form): Form<TokenForm>,
+) -> Result<Json<Introspection>, (StatusCode, String)> {
+    // Metadata about other people's gifts is only for admins
+    check_admin(&state.config, &headers)?;
+
+    // Anything that would not unwrap is simply inactive
+    let Ok((claim, _)) = open_gift(&state, &form.token).await else {
+        return Ok(Json(Introspection::default()));
+    };
+    if state
+        .revocations
+        .is_revoked(&claim.jti)
+        .await
+        .map_err(db_error)?
+    {
+        return Ok(Json(Introspection::default()));
+    }
+
+    Ok(Json(Introspection {
+        active: true,
+        token_type: Some("gift"),
+        jti: Some(claim.jti),
+        iat: Some(claim.iat),
+        nbf: Some(claim.nbf),
+        exp: Some(claim.exp),
+    }))
+}
@@ -0,0 +1,257 @@
+use std::{env, fmt};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN},
+    hkdf::{Salt, HKDF_SHA256},
+    rand::{SecureRandom, SystemRandom},
+};
+use serde::{Deserialize, Serialize};
+
+// Direct encryption with a shared key, the only mode gifts use
+const ALG: &str = "dir";
+const ENC: &str = "A256GCM";
+
+// Content types, gifts hold a signed JWT and stored keys their secret or PEM
+const JWT_CTY: &str = "JWT";
+const KEY_CTY: &str = "key";
+
+#[derive(Serialize, Deserialize)]
+struct JweHeader {
+    alg: String,
+    enc: String,
+    // Content type, so a stored key can never be opened as a gift
+    cty: String,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum JweError {
+    Malformed,
+    UnsupportedAlgorithm,
+    DecryptionFailed,
+}
+
+impl fmt::Display for JweError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JweError::Malformed => f.write_str("Malformed JWE"),
+            JweError::UnsupportedAlgorithm => f.write_str("Unsupported JWE algorithm"),
+            JweError::DecryptionFailed => f.write_str("Decryption failed"),
+        }
+    }
+}
+
+/// Encrypts signed gifts into compact JWEs so their contents stay hidden
+pub struct GiftCipher {
+    key: Option<LessSafeKey>,
+    rng: SystemRandom,
+}
+
+impl GiftCipher {
+    pub fn from_env() -> GiftCipher {
+        // Use a dedicated key if configured, otherwise derive one from the signing secret
+        let key = match env::var("GIFT_ENCRYPTION_KEY") {
+            Ok(encoded) => URL_SAFE_NO_PAD
+                .decode(encoded.trim_end_matches('='))
+                .ok()
+                .and_then(|bytes| UnboundKey::new(&AES_256_GCM, &bytes).ok())
+                .or_else(|| {
+                    println!("GIFT_ENCRYPTION_KEY must be 32 bytes of base64url");
+                    None
+                }),
+            Err(_) => env::var("JWT_SECRET").ok().map(|secret| {
+                Salt::new(HKDF_SHA256, b"gift-encryption")
+                    .extract(secret.as_bytes())
+                    .expand(&[ENC.as_bytes()], &AES_256_GCM)
+                    .expect("Invalid key length")
+                    .into()
+            }),
+        };
+        GiftCipher {
+            key: key.map(LessSafeKey::new),
+            rng: SystemRandom::new(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.key.is_some()
+    }
+
+    pub fn encrypt(&self, jwt: &str) -> Option<String> {
+        self.seal(JWT_CTY, jwt)
+    }
+
+    pub fn decrypt(&self, token: &str) -> Result<String, JweError> {
+        self.open(JWT_CTY, token)
+    }
+
+    /// Encrypts the secret or PEM of a key before it is stored
+    pub fn encrypt_key(&self, material: &str) -> Option<String> {
+        self.seal(KEY_CTY, material)
+    }
+
+    pub fn decrypt_key(&self, stored: &str) -> Result<String, JweError> {
+        self.open(KEY_CTY, stored)
+    }
+
+    fn seal(&self, cty: &str, plaintext: &str) -> Option<String> {
+        let key = self.key.as_ref()?;
+        let header = JweHeader {
+            alg: ALG.to_string(),
+            enc: ENC.to_string(),
+            cty: cty.to_string(),
+        };
+        let header = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&header).ok()?);
+
+        // Fresh nonce for every gift, the encoded header is authenticated too
+        let mut iv = [0; NONCE_LEN];
+        self.rng.fill(&mut iv).ok()?;
+        let mut ciphertext = plaintext.as_bytes().to_vec();
+        let tag = key
+            .seal_in_place_separate_tag(
+                Nonce::assume_unique_for_key(iv),
+                Aad::from(header.as_bytes()),
+                &mut ciphertext,
+            )
+            .ok()?;
+
+        // Compact serialization, the encrypted key is empty for direct encryption
+        Some(format!(
+            "{}..{}.{}.{}",
+            header,
+            URL_SAFE_NO_PAD.encode(iv),
+            URL_SAFE_NO_PAD.encode(ciphertext),
+            URL_SAFE_NO_PAD.encode(tag)
+        ))
+    }
+
+    fn open(&self, cty: &str, token: &str) -> Result<String, JweError> {
+        let [header, encrypted_key, iv, ciphertext, tag] = token
+            .split('.')
+            .collect::<Vec<_>>()
+            .try_into()
+            .map_err(|_| JweError::Malformed)?;
+        let decode = |part: &str| {
+            URL_SAFE_NO_PAD
+                .decode(part)
+                .map_err(|_| JweError::Malformed)
+        };
+
+        let parsed: JweHeader =
+            serde_json::from_slice(&decode(header)?).map_err(|_| JweError::Malformed)?;
+        if parsed.alg != ALG || parsed.enc != ENC || !encrypted_key.is_empty() {
+            return Err(JweError::UnsupportedAlgorithm);
+        }
+        if parsed.cty != cty {
+            return Err(JweError::Malformed);
+        }
+        let key = self.key.as_ref().ok_or(JweError::DecryptionFailed)?;
+        let nonce =
+            Nonce::try_assume_unique_for_key(&decode(iv)?).map_err(|_| JweError::Malformed)?;
+
+        // Ring expects the tag appended to the ciphertext
+        let mut in_out = decode(ciphertext)?;
+        in_out.extend(decode(tag)?);
+        let plaintext = key
+            .open_in_place(nonce, Aad::from(header.as_bytes()), &mut in_out)
+            .map_err(|_| JweError::DecryptionFailed)?;
+        String::from_utf8(plaintext.to_vec()).map_err(|_| JweError::Malformed)
+    }
+}
+
+/// Compact JWEs have five parts, signed JWTs three
+pub fn is_jwe(token: &str) -> bool {
+    token.split('.').count() == 5
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_key(byte: u8) -> GiftCipher {
+        let key = UnboundKey::new(&AES_256_GCM, &[byte; 32]).unwrap();
+        GiftCipher {
+            key: Some(LessSafeKey::new(key)),
+            rng: SystemRandom::new(),
+        }
+    }
+
+    fn tamper(token: &str, part: usize) -> String {
+        // Flip one bit of a decoded part, keeping the encoding valid
+        let mut parts: Vec<String> = token.split('.').map(str::to_string).collect();
+        let mut bytes = URL_SAFE_NO_PAD.decode(&parts[part]).unwrap();
+        bytes[0] ^= 1;
+        parts[part] = URL_SAFE_NO_PAD.encode(bytes);
+        parts.join(".")
+    }
+
+    #[test]
+    fn round_trip() {
+        let cipher = with_key(7);
+        let token = cipher.encrypt("a.b.c").unwrap();
+        assert!(is_jwe(&token));
+        assert!(!token.contains("a.b.c"));
+        assert_eq!(cipher.decrypt(&token).unwrap(), "a.b.c");
+        // Every gift gets a fresh nonce
+        assert_ne!(cipher.encrypt("a.b.c").unwrap(), token);
+    }
+
+    #[test]
+    fn tampering() {
+        let cipher = with_key(7);
+        let token = cipher.encrypt("a.b.c").unwrap();
+        for part in [0, 2] {
+            assert!(cipher.decrypt(&tamper(&token, part)).is_err());
+        }
+        assert_eq!(
+            cipher.decrypt(&tamper(&token, 3)),
+            Err(JweError::DecryptionFailed)
+        );
+        assert_eq!(
+            cipher.decrypt(&tamper(&token, 4)),
+            Err(JweError::DecryptionFailed)
+        );
+        assert_eq!(with_key(8).decrypt(&token), Err(JweError::DecryptionFailed));
+        assert_eq!(cipher.decrypt("a.b.c"), Err(JweError::Malformed));
+    }
+
+    #[test]
+    fn unsupported_algorithm() {
+        let cipher = with_key(7);
+        let token = cipher.encrypt("a.b.c").unwrap();
+        let (_, rest) = token.split_once('.').unwrap();
+        let header = URL_SAFE_NO_PAD.encode(r#"{"alg":"RSA-OAEP","enc":"A256GCM","cty":"JWT"}"#);
+        assert_eq!(
+            cipher.decrypt(&format!("{}.{}", header, rest)),
+            Err(JweError::UnsupportedAlgorithm)
+        );
+        // Direct encryption has no encrypted key
+        let with_key = token.replacen("..", ".a.", 1);
+        assert_eq!(
+            cipher.decrypt(&with_key),
+            Err(JweError::UnsupportedAlgorithm)
+        );
+    }
+
+    #[test]
+    fn stored_keys() {
+        let cipher = with_key(7);
+        let stored = cipher.encrypt_key("secret").unwrap();
+        assert_eq!(cipher.decrypt_key(&stored).unwrap(), "secret");
+        // Neither can be opened as the other
+        assert!(cipher.decrypt(&stored).is_err());
+        assert!(cipher
+            .decrypt_key(&cipher.encrypt("a.b.c").unwrap())
+            .is_err());
+    }
+
+    #[test]
+    fn disabled() {
+        let cipher = GiftCipher {
+            key: None,
+            rng: SystemRandom::new(),
+        };
+        assert!(!cipher.is_enabled());
+        assert!(cipher.encrypt("a.b.c").is_none());
+    }
+}
@@ -0,0 +1,330 @@
+use std::{collections::BTreeMap, env, fmt, fs, str::FromStr};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{
+    get_current_timestamp,
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, EllipticCurveKeyParameters, Jwk,
+        JwkSet, KeyAlgorithm, OctetKeyPairParameters, PublicKeyUse, RSAKeyParameters,
+    },
+    Algorithm, DecodingKey, EncodingKey,
+};
+use ring::{
+    rand::SystemRandom,
+    rsa::PublicKeyComponents,
+    signature::{self, EcdsaKeyPair, Ed25519KeyPair, KeyPair, RsaKeyPair},
+};
+use serde::Serialize;
+
+// Id of the key seeded from JWT_SECRET, also used for tokens without a `kid`
+pub const DEFAULT_KID: &str = "default";
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyStatus {
+    // Signs new gifts and verifies old ones
+    Active,
+    // Only verifies gifts
+    Standby,
+    // No longer accepted at all
+    Retired,
+}
+
+impl KeyStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            KeyStatus::Active => "active",
+            KeyStatus::Standby => "standby",
+            KeyStatus::Retired => "retired",
+        }
+    }
+}
+
+impl FromStr for KeyStatus {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "active" => Ok(KeyStatus::Active),
+            "standby" => Ok(KeyStatus::Standby),
+            "retired" => Ok(KeyStatus::Retired),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Key {
+    alg: Algorithm,
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    // Published in the JWKS, only set for asymmetric keys
+    jwk: Option<Jwk>,
+    status: KeyStatus,
+    created: u64,
+}
+
+impl Key {
+    pub fn alg(&self) -> Algorithm {
+        self.alg
+    }
+
+    pub fn hmac(secret: &str) -> Key {
+        Key {
+            alg: Algorithm::HS256,
+            encoding: EncodingKey::from_secret(secret.as_bytes()),
+            decoding: DecodingKey::from_secret(secret.as_bytes()),
+            jwk: None,
+            status: KeyStatus::Standby,
+            created: get_current_timestamp(),
+        }
+    }
+
+    /// Loads a PEM private key for RS*, PS*, ES256/ES384 or EdDSA
+    pub fn from_pem(kid: &str, alg: Algorithm, pem: &str) -> Result<Key, String> {
+        let parsed = pem::parse(pem).map_err(|err| err.to_string())?;
+        let der = parsed.contents();
+        let rejected = |err: ring::error::KeyRejected| err.to_string();
+
+        // Work out the public half of the key and how to sign with the private one
+        let (encoding, params) = match alg {
+            Algorithm::RS256
+            | Algorithm::RS384
+            | Algorithm::RS512
+            | Algorithm::PS256
+            | Algorithm::PS384
+            | Algorithm::PS512 => {
+                let pair = match parsed.tag() {
+                    "RSA PRIVATE KEY" => RsaKeyPair::from_der(der),
+                    _ => RsaKeyPair::from_pkcs8(der),
+                }
+                .map_err(rejected)?;
+                let public: PublicKeyComponents<Vec<u8>> = pair.public().into();
+                let params = AlgorithmParameters::RSA(RSAKeyParameters {
+                    n: URL_SAFE_NO_PAD.encode(public.n),
+                    e: URL_SAFE_NO_PAD.encode(public.e),
+                    ..Default::default()
+                });
+                (EncodingKey::from_rsa_pem(pem.as_bytes()), params)
+            }
+            Algorithm::ES256 | Algorithm::ES384 => {
+                let (signing, curve) = match alg {
+                    Algorithm::ES256 => (
+                        &signature::ECDSA_P256_SHA256_FIXED_SIGNING,
+                        EllipticCurve::P256,
+                    ),
+                    _ => (
+                        &signature::ECDSA_P384_SHA384_FIXED_SIGNING,
+                        EllipticCurve::P384,
+                    ),
+                };
+                let pair = EcdsaKeyPair::from_pkcs8(signing, der, &SystemRandom::new())
+                    .map_err(rejected)?;
+                // Uncompressed point, 0x04 followed by x and y
+                let point = &pair.public_key().as_ref()[1..];
+                let (x, y) = point.split_at(point.len() / 2);
+                let params = AlgorithmParameters::EllipticCurve(EllipticCurveKeyParameters {
+                    curve,
+                    x: URL_SAFE_NO_PAD.encode(x),
+                    y: URL_SAFE_NO_PAD.encode(y),
+                    ..Default::default()
+                });
+                (EncodingKey::from_ec_pem(pem.as_bytes()), params)
+            }
+            Algorithm::EdDSA => {
+                let pair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(der).map_err(rejected)?;
+                let params = AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                    curve: EllipticCurve::Ed25519,
+                    x: URL_SAFE_NO_PAD.encode(pair.public_key()),
+                    ..Default::default()
+                });
+                (EncodingKey::from_ed_pem(pem.as_bytes()), params)
+            }
+            _ => return Err(format!("{:?} is not an asymmetric algorithm", alg)),
+        };
+
+        let jwk = Jwk {
+            common: CommonParameters {
+                public_key_use: Some(PublicKeyUse::Signature),
+                key_algorithm: format!("{:?}", alg).parse::<KeyAlgorithm>().ok(),
+                key_id: Some(kid.to_string()),
+                ..Default::default()
+            },
+            algorithm: params,
+        };
+        Ok(Key {
+            alg,
+            encoding: encoding.map_err(|err| err.to_string())?,
+            decoding: DecodingKey::from_jwk(&jwk).map_err(|err| err.to_string())?,
+            jwk: Some(jwk),
+            status: KeyStatus::Standby,
+            created: get_current_timestamp(),
+        })
+    }
+}
+
+#[derive(Serialize)]
+pub struct KeyInfo {
+    pub kid: String,
+    pub alg: Algorithm,
+    pub status: KeyStatus,
+    pub created: u64,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum KeyError {
+    AlreadyExists,
+    NotFound,
+    Retired,
+    Active,
+}
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyError::AlreadyExists => f.write_str("Key already exists"),
+            KeyError::NotFound => f.write_str("Key not found"),
+            KeyError::Retired => f.write_str("Key is retired"),
+            KeyError::Active => f.write_str("Key is active"),
+        }
+    }
+}
+
+/// Signing keys identified by `kid`, exactly one of which signs new gifts
+#[derive(Default, Clone)]
+pub struct KeySet {
+    keys: BTreeMap<String, Key>,
+}
+
+impl KeySet {
+    pub fn from_env() -> KeySet {
+        // Seed the set with the secret from .env as the active key
+        let mut keys = KeySet::default();
+        match env::var("JWT_SECRET") {
+            Ok(secret) => {
+                keys.add(DEFAULT_KID.to_string(), Key::hmac(&secret)).ok();
+                keys.activate(DEFAULT_KID).ok();
+            }
+            Err(err) => println!("{:?}", err),
+        }
+
+        // A configured private key takes over signing, the secret still verifies old gifts
+        if let Ok(alg) = env::var("GIFT_SIGNING_ALG") {
+            match signing_key_from_env(&alg) {
+                Ok((kid, key)) => {
+                    keys.add(kid.clone(), key).ok();
+                    keys.activate(&kid).ok();
+                }
+                Err(err) => println!("Invalid signing key: {}", err),
+            }
+        }
+        keys
+    }
+
+    pub fn add(&mut self, kid: String, key: Key) -> Result<(), KeyError> {
+        if self.keys.contains_key(&kid) {
+            return Err(KeyError::AlreadyExists);
+        }
+        self.keys.insert(kid, key);
+        Ok(())
+    }
+
+    pub fn activate(&mut self, kid: &str) -> Result<(), KeyError> {
+        match self.keys.get(kid).map(|k| k.status) {
+            None => return Err(KeyError::NotFound),
+            Some(KeyStatus::Retired) => return Err(KeyError::Retired),
+            Some(_) => {}
+        }
+        // The previous active key keeps verifying the gifts it signed
+        for (id, key) in self.keys.iter_mut() {
+            if key.status == KeyStatus::Active && id != kid {
+                key.status = KeyStatus::Standby;
+            }
+        }
+        if let Some(key) = self.keys.get_mut(kid) {
+            key.status = KeyStatus::Active;
+        }
+        Ok(())
+    }
+
+    pub fn retire(&mut self, kid: &str) -> Result<(), KeyError> {
+        let key = self.keys.get_mut(kid).ok_or(KeyError::NotFound)?;
+        // Another key has to be activated first so wrapping keeps working
+        if key.status == KeyStatus::Active {
+            return Err(KeyError::Active);
+        }
+        key.status = KeyStatus::Retired;
+        Ok(())
+    }
+
+    /// Puts a key back in the status it was saved with
+    ///
+    /// The active key is only replaced by activating another, so signing keeps working
+    pub fn restore(&mut self, kid: &str, status: KeyStatus, created: u64) {
+        let Some(key) = self.keys.get_mut(kid) else {
+            return;
+        };
+        key.created = created;
+        match status {
+            KeyStatus::Active => {
+                self.activate(kid).ok();
+            }
+            _ if key.status == KeyStatus::Active => {}
+            _ => key.status = status,
+        }
+    }
+
+    pub fn list(&self) -> Vec<KeyInfo> {
+        self.keys
+            .iter()
+            .map(|(kid, key)| KeyInfo {
+                kid: kid.clone(),
+                alg: key.alg,
+                status: key.status,
+                created: key.created,
+            })
+            .collect()
+    }
+
+    /// The key new gifts are signed with, along with its `kid` and algorithm
+    pub fn signing_key(&self) -> Option<(String, Algorithm, EncodingKey)> {
+        self.keys
+            .iter()
+            .find(|(_, key)| key.status == KeyStatus::Active)
+            .map(|(kid, key)| (kid.clone(), key.alg, key.encoding.clone()))
+    }
+
+    /// The key named by a token's `kid`, unless it has been retired
+    pub fn decoding_key(&self, kid: Option<&str>) -> Option<(Algorithm, DecodingKey)> {
+        self.keys
+            .get(kid.unwrap_or(DEFAULT_KID))
+            .filter(|key| key.status != KeyStatus::Retired)
+            .map(|key| (key.alg, key.decoding.clone()))
+    }
+
+    /// Public halves of the asymmetric keys that are still accepted
+    pub fn jwks(&self) -> JwkSet {
+        let keys = self
+            .keys
+            .values()
+            .filter(|key| key.status != KeyStatus::Retired)
+            .filter_map(|key| key.jwk.clone())
+            .collect();
+        JwkSet { keys }
+    }
+}
+
+fn signing_key_from_env(alg: &str) -> Result<(String, Key), String> {
+    let alg = alg.parse::<Algorithm>().map_err(|err| err.to_string())?;
+    let kid = env::var("GIFT_SIGNING_KID").unwrap_or_else(|_| "signing".to_string());
+
+    // The key is either the PEM itself or a path to it
+    let pem = env::var("GIFT_SIGNING_KEY").map_err(|err| err.to_string())?;
+    let pem = if pem.trim_start().starts_with("-----BEGIN") {
+        pem
+    } else {
+        fs::read_to_string(&pem).map_err(|err| format!("{}: {}", pem, err))?
+    };
+    let key = Key::from_pem(&kid, alg, &pem)?;
+    Ok((kid, key))
+}
@@ -0,0 +1,161 @@
+use jsonwebtoken::Algorithm;
+use sqlx::{prelude::FromRow, Error, PgPool};
+
+use super::{
+    jwe::GiftCipher,
+    keys::{Key, KeySet, KeyStatus},
+};
+
+const CREATE_GIFT_KEYS_QUERY: &str = "
+        CREATE TABLE IF NOT EXISTS gift_keys (
+            kid TEXT PRIMARY KEY,
+            alg TEXT NOT NULL,
+            secret TEXT,
+            pem TEXT,
+            status TEXT NOT NULL,
+            created BIGINT NOT NULL
+        );
+        ";
+
+#[derive(FromRow)]
+struct StoredKey {
+    kid: String,
+    alg: String,
+    // Encrypted key material of keys added at runtime, keys from the environment have none
+    secret: Option<String>,
+    pem: Option<String>,
+    status: String,
+    created: i64,
+}
+
+/// Keys added through the admin API and the status of every key, kept across restarts
+pub enum KeyStore {
+    Database(PgPool),
+    // Nothing is kept when there is no database
+    Memory,
+}
+
+impl KeyStore {
+    pub async fn new(pool: Option<&PgPool>, cipher: &GiftCipher) -> KeyStore {
+        let Some(pool) = pool else {
+            return KeyStore::Memory;
+        };
+        // Key material is only ever stored encrypted
+        if !cipher.is_enabled() {
+            println!("Challenge 16 keys are not stored without an encryption key!");
+            return KeyStore::Memory;
+        }
+
+        // Create table, keeping keys in memory only if that fails
+        match sqlx::query(CREATE_GIFT_KEYS_QUERY).execute(pool).await {
+            Ok(_) => KeyStore::Database(pool.clone()),
+            Err(e) => {
+                eprintln!("{:?}", e);
+                println!("Challenge 16 key store init failed!");
+                KeyStore::Memory
+            }
+        }
+    }
+
+    /// Adds the stored keys to those from the environment and restores their status
+    ///
+    /// A key activated through the admin API stays active over the configured one
+    pub async fn load(&self, keys: &mut KeySet, cipher: &GiftCipher) -> Result<(), Error> {
+        let KeyStore::Database(pool) = self else {
+            return Ok(());
+        };
+        let mut stored: Vec<StoredKey> = sqlx::query_as(
+            "SELECT kid, alg, secret, pem, status, created FROM gift_keys ORDER BY created",
+        )
+        .fetch_all(pool)
+        .await?;
+
+        // Restore the active key first, so it replaces whichever the environment chose
+        // and that one can then be retired too
+        stored.sort_by_key(|row| row.status != KeyStatus::Active.as_str());
+        for row in stored {
+            let decrypt = |stored: &str| cipher.decrypt_key(stored).map_err(|err| err.to_string());
+            let key = match (row.secret, row.pem) {
+                (Some(secret), _) => decrypt(&secret).map(|secret| Some(Key::hmac(&secret))),
+                (None, Some(pem)) => row
+                    .alg
+                    .parse::<Algorithm>()
+                    .map_err(|err| err.to_string())
+                    .and_then(|alg| Key::from_pem(&row.kid, alg, &decrypt(&pem)?))
+                    .map(Some),
+                // Keys from the environment
+                (None, None) => Ok(None),
+            };
+            match key {
+                Ok(Some(key)) => {
+                    keys.add(row.kid.clone(), key).ok();
+                }
+                Ok(None) => {}
+                Err(err) => println!("Key {} could not be loaded: {}", row.kid, err),
+            }
+            match row.status.parse::<KeyStatus>() {
+                Ok(status) => keys.restore(&row.kid, status, row.created as u64),
+                Err(_) => println!("Invalid status for key {}", row.kid),
+            }
+        }
+        Ok(())
+    }
+
+    /// Saves a key added at runtime, the secret or PEM is encrypted first
+    pub async fn add(
+        &self,
+        cipher: &GiftCipher,
+        kid: &str,
+        alg: Algorithm,
+        secret: Option<&str>,
+        pem: Option<&str>,
+    ) -> Result<(), Error> {
+        let KeyStore::Database(pool) = self else {
+            return Ok(());
+        };
+        let encrypt = |material: Option<&str>| {
+            material
+                .map(|material| {
+                    cipher
+                        .encrypt_key(material)
+                        .ok_or_else(|| Error::Encode("Key encryption failed".into()))
+                })
+                .transpose()
+        };
+        let (secret, pem) = (encrypt(secret)?, encrypt(pem)?);
+        sqlx::query(
+            "INSERT INTO gift_keys (kid, alg, secret, pem, status, created)
+            VALUES ($1, $2, $3, $4, $5, EXTRACT(EPOCH FROM NOW())::BIGINT)
+            ON CONFLICT (kid) DO UPDATE SET alg = $2, secret = $3, pem = $4, status = $5",
+        )
+        .bind(kid)
+        .bind(format!("{:?}", alg))
+        .bind(secret)
+        .bind(pem)
+        .bind(KeyStatus::Standby.as_str())
+        .execute(pool)
+        .await
+        .map(|_| ())
+    }
+
+    /// Saves the status of every key, including those from the environment
+    pub async fn save_statuses(&self, keys: &KeySet) -> Result<(), Error> {
+        let KeyStore::Database(pool) = self else {
+            return Ok(());
+        };
+        let mut tx = pool.begin().await?;
+        for key in keys.list() {
+            sqlx::query(
+                "INSERT INTO gift_keys (kid, alg, status, created) VALUES ($1, $2, $3, $4)
+                ON CONFLICT (kid) DO UPDATE SET status = $3",
+            )
+            .bind(&key.kid)
+            .bind(format!("{:?}", key.alg))
+            .bind(key.status.as_str())
+            .bind(key.created as i64)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await
+    }
+}
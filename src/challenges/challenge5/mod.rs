@@ -1,14 +1,86 @@
+use std::sync::Arc;
+
+use admin::{activate_key, add_key, list_keys, retire_key};
 use axum::{
-    routing::{get, post},
+    routing::{delete, get, post},
     Router,
 };
-use endpoints::{decode_jwt, unwrap, wrap};
+use config::GiftConfig;
+use endpoints::{decode_jwt, jwks, unwrap, wrap};
+use inspect::inspect;
+use introspect::{introspect, revoke};
+use jwe::GiftCipher;
+use keys::KeySet;
+use keystore::KeyStore;
+use revocation::Revocations;
+use sqlx::PgPool;
+use tokio::sync::RwLock;
+use trusted::TrustedKeys;
+
+pub use config::CONFIG_VARS;
 
+mod admin;
+mod config;
 mod endpoints;
+mod inspect;
+mod introspect;
+mod jwe;
+mod keys;
+mod keystore;
+mod payload;
+mod revocation;
+mod trusted;
+
+pub struct GiftStateInner {
+    config: GiftConfig,
+    keys: RwLock<KeySet>,
+    store: KeyStore,
+    trusted: TrustedKeys,
+    cipher: GiftCipher,
+    revocations: Revocations,
+}
+
+pub type GiftState = Arc<GiftStateInner>;
+
+/// Loads the gift settings and keys, shared by the /16 and /.well-known routes
+///
+/// Keys added at runtime and revocations are kept in memory when there is no database
+pub async fn state(pool: Option<&PgPool>) -> GiftState {
+    // Keys from the environment, then those added and activated through the admin API
+    let cipher = GiftCipher::from_env();
+    let store = KeyStore::new(pool, &cipher).await;
+    let mut keys = KeySet::from_env();
+    if let Err(e) = store.load(&mut keys, &cipher).await {
+        eprintln!("{:?}", e);
+        println!("Challenge 16 stored keys could not be loaded!");
+    }
 
-pub fn router() -> Router {
+    Arc::new(GiftStateInner {
+        config: GiftConfig::from_env(),
+        keys: RwLock::new(keys),
+        store,
+        trusted: TrustedKeys::from_env(),
+        cipher,
+        revocations: Revocations::new(pool).await,
+    })
+}
+
+pub fn router(state: &GiftState) -> Router {
     Router::new()
         .route("/wrap", post(wrap))
         .route("/unwrap", get(unwrap))
         .route("/decode", post(decode_jwt))
+        .route("/revoke", post(revoke))
+        .route("/introspect", post(introspect))
+        .route("/inspect", post(inspect))
+        .route("/keys", get(list_keys).post(add_key))
+        .route("/keys/:kid/activate", post(activate_key))
+        .route("/keys/:kid", delete(retire_key))
+        .with_state(state.clone())
+}
+
+pub fn well_known(state: &GiftState) -> Router {
+    Router::new()
+        .route("/jwks.json", get(jwks))
+        .with_state(state.clone())
 }
@@ -0,0 +1,168 @@
+use std::io::{Read, Write};
+
+use axum::{
+    body::Bytes,
+    http::header::{CONTENT_DISPOSITION, CONTENT_TYPE, X_CONTENT_TYPE_OPTIONS},
+    response::{IntoResponse, Response},
+    Json,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+use serde_json::Value;
+
+// Value of the `zip` claim for DEFLATE compressed bodies, as in JWE
+const DEFLATE: &str = "DEF";
+
+// Largest body accepted after decompression, so small gifts can not expand without bound
+const MAX_INFLATED: u64 = 1024 * 1024;
+
+/// The contents of a gift
+pub enum Payload {
+    Json(Value),
+    Text { content_type: String, text: String },
+    Binary { content_type: String, data: Vec<u8> },
+}
+
+/// How a payload is stored in the claims
+pub struct EncodedBody {
+    pub body: Value,
+    // Content type, left out for JSON
+    pub ct: Option<String>,
+    pub zip: Option<String>,
+}
+
+impl Payload {
+    pub fn from_request(content_type: &str, body: Bytes) -> Result<Payload, String> {
+        // Anything that is neither JSON nor text is kept as opaque bytes
+        if content_type.starts_with("application/json") {
+            serde_json::from_slice(&body)
+                .map(Payload::Json)
+                .map_err(|err| format!("Invalid JSON: {}", err))
+        } else if content_type.starts_with("text/") {
+            String::from_utf8(body.to_vec())
+                .map(|text| Payload::Text {
+                    content_type: content_type.to_string(),
+                    text,
+                })
+                .map_err(|_| "Text is not valid UTF-8".to_string())
+        } else {
+            Ok(Payload::Binary {
+                content_type: content_type.to_string(),
+                data: body.to_vec(),
+            })
+        }
+    }
+
+    pub fn encode(self, compress: bool) -> Result<EncodedBody, String> {
+        let ct = match &self {
+            Payload::Json(_) => None,
+            Payload::Text { content_type, .. } | Payload::Binary { content_type, .. } => {
+                Some(content_type.clone())
+            }
+        };
+
+        // Compressed bodies are always base64 of the deflated bytes
+        if compress {
+            let bytes = match self {
+                Payload::Json(value) => {
+                    serde_json::to_vec(&value).map_err(|err| err.to_string())?
+                }
+                Payload::Text { text, .. } => text.into_bytes(),
+                Payload::Binary { data, .. } => data,
+            };
+            let mut encoder = DeflateEncoder::new(Vec::new(), Compression::best());
+            encoder.write_all(&bytes).map_err(|err| err.to_string())?;
+            let deflated = encoder.finish().map_err(|err| err.to_string())?;
+            return Ok(EncodedBody {
+                body: Value::String(URL_SAFE_NO_PAD.encode(deflated)),
+                ct,
+                zip: Some(DEFLATE.to_string()),
+            });
+        }
+
+        let body = match self {
+            Payload::Json(value) => value,
+            Payload::Text { text, .. } => Value::String(text),
+            Payload::Binary { data, .. } => Value::String(URL_SAFE_NO_PAD.encode(data)),
+        };
+        Ok(EncodedBody {
+            body,
+            ct,
+            zip: None,
+        })
+    }
+
+    pub fn decode(body: &Value, ct: Option<&str>, zip: Option<&str>) -> Result<Payload, String> {
+        let bytes = match zip {
+            None => None,
+            Some(DEFLATE) => {
+                let deflated = body
+                    .as_str()
+                    .and_then(|body| URL_SAFE_NO_PAD.decode(body).ok())
+                    .ok_or("Compressed body is not base64")?;
+                let mut bytes = Vec::new();
+                DeflateDecoder::new(deflated.as_slice())
+                    .take(MAX_INFLATED + 1)
+                    .read_to_end(&mut bytes)
+                    .map_err(|err| format!("Invalid compressed body: {}", err))?;
+                if bytes.len() as u64 > MAX_INFLATED {
+                    return Err("Compressed body too large".to_string());
+                }
+                Some(bytes)
+            }
+            Some(zip) => return Err(format!("Unsupported compression: {}", zip)),
+        };
+
+        match (ct, bytes) {
+            (None, None) => Ok(Payload::Json(body.clone())),
+            (None, Some(bytes)) => serde_json::from_slice(&bytes)
+                .map(Payload::Json)
+                .map_err(|err| format!("Invalid JSON: {}", err)),
+            (Some(content_type), bytes) if content_type.starts_with("text/") => {
+                let text = match bytes {
+                    Some(bytes) => String::from_utf8(bytes).map_err(|err| err.to_string())?,
+                    None => body
+                        .as_str()
+                        .ok_or("Text body is not a string")?
+                        .to_string(),
+                };
+                Ok(Payload::Text {
+                    content_type: content_type.to_string(),
+                    text,
+                })
+            }
+            (Some(content_type), bytes) => {
+                let data = match bytes {
+                    Some(bytes) => bytes,
+                    None => body
+                        .as_str()
+                        .and_then(|body| URL_SAFE_NO_PAD.decode(body).ok())
+                        .ok_or("Binary body is not base64")?,
+                };
+                Ok(Payload::Binary {
+                    content_type: content_type.to_string(),
+                    data,
+                })
+            }
+        }
+    }
+}
+
+impl IntoResponse for Payload {
+    fn into_response(self) -> Response {
+        // Hand the gift back the way it was wrapped, but as a download so content
+        // like HTML or SVG is never rendered on this site
+        let headers = |content_type| {
+            [
+                (CONTENT_TYPE, content_type),
+                (X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+                (CONTENT_DISPOSITION, "attachment".to_string()),
+            ]
+        };
+        match self {
+            Payload::Json(value) => Json(value).into_response(),
+            Payload::Text { content_type, text } => (headers(content_type), text).into_response(),
+            Payload::Binary { content_type, data } => (headers(content_type), data).into_response(),
+        }
+    }
+}
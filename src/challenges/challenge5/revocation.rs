@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+
+use jsonwebtoken::get_current_timestamp;
+use sqlx::{Error, PgPool};
+use tokio::sync::Mutex;
+
+const CREATE_REVOKED_GIFTS_QUERY: &str = "
+        CREATE TABLE IF NOT EXISTS revoked_gifts (
+            jti TEXT PRIMARY KEY,
+            expires_at BIGINT NOT NULL,
+            revoked_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+        );
+        ";
+
+/// Ids of gifts that were revoked before they expired
+pub enum Revocations {
+    Database(PgPool),
+    // Revoked ids and their expiry, used when there is no database
+    Memory(Mutex<HashMap<String, u64>>),
+}
+
+impl Revocations {
+    pub async fn new(pool: Option<&PgPool>) -> Revocations {
+        let Some(pool) = pool else {
+            return Revocations::Memory(Mutex::new(HashMap::new()));
+        };
+
+        // Create table, keeping revocations in memory if that fails
+        match sqlx::query(CREATE_REVOKED_GIFTS_QUERY).execute(pool).await {
+            Ok(_) => Revocations::Database(pool.clone()),
+            Err(e) => {
+                eprintln!("{:?}", e);
+                println!("Challenge 16 revocation list init failed!");
+                Revocations::Memory(Mutex::new(HashMap::new()))
+            }
+        }
+    }
+
+    pub async fn revoke(&self, jti: &str, exp: u64) -> Result<(), Error> {
+        // Expired gifts are rejected anyway, so their entries can be dropped
+        let now = get_current_timestamp();
+        match self {
+            Revocations::Database(pool) => {
+                sqlx::query("DELETE FROM revoked_gifts WHERE expires_at < $1")
+                    .bind(now as i64)
+                    .execute(pool)
+                    .await?;
+                sqlx::query(
+                    "INSERT INTO revoked_gifts (jti, expires_at) VALUES ($1, $2)
+                    ON CONFLICT (jti) DO NOTHING",
+                )
+                .bind(jti)
+                .bind(exp as i64)
+                .execute(pool)
+                .await?;
+            }
+            Revocations::Memory(revoked) => {
+                let mut revoked = revoked.lock().await;
+                revoked.retain(|_, expires_at| *expires_at >= now);
+                revoked.insert(jti.to_string(), exp);
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn is_revoked(&self, jti: &str) -> Result<bool, Error> {
+        match self {
+            Revocations::Database(pool) => {
+                sqlx::query("SELECT 1 FROM revoked_gifts WHERE jti = $1")
+                    .bind(jti)
+                    .fetch_optional(pool)
+                    .await
+                    .map(|row| row.is_some())
+            }
+            Revocations::Memory(revoked) => Ok(revoked.lock().await.contains_key(jti)),
+        }
+    }
+}
@@ -0,0 +1,259 @@
+use std::{env, fs, path::Path};
+
+use jsonwebtoken::{
+    jwk::{AlgorithmParameters, EllipticCurve, JwkSet},
+    Algorithm, DecodingKey,
+};
+use serde::Deserialize;
+
+// Public key used when no trusted keys are configured
+const EMBEDDED_KEY: &[u8] = include_bytes!("key.pem");
+
+#[derive(Copy, Clone, PartialEq)]
+enum Family {
+    Rsa,
+    Ec,
+    Ed,
+}
+
+fn family(alg: Algorithm) -> Option<Family> {
+    match alg {
+        Algorithm::RS256
+        | Algorithm::RS384
+        | Algorithm::RS512
+        | Algorithm::PS256
+        | Algorithm::PS384
+        | Algorithm::PS512 => Some(Family::Rsa),
+        Algorithm::ES256 | Algorithm::ES384 => Some(Family::Ec),
+        Algorithm::EdDSA => Some(Family::Ed),
+        // HMAC secrets can not be shared as public keys
+        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => None,
+    }
+}
+
+/// One entry of the trusted keys file
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum KeySource {
+    // A PEM public key with the algorithms it may verify
+    Pem {
+        kid: Option<String>,
+        pem: String,
+        algs: Vec<Algorithm>,
+    },
+    // A JWKS document, each key limited to its own `alg` unless overridden
+    Jwks {
+        jwks: String,
+        algs: Option<Vec<Algorithm>>,
+    },
+}
+
+pub struct TrustedKey {
+    pub kid: Option<String>,
+    pub algs: Vec<Algorithm>,
+    pub key: DecodingKey,
+}
+
+#[derive(Debug)]
+pub enum KeyLookupError {
+    UnknownKid,
+    AlgorithmNotAllowed,
+}
+
+/// Public keys `/16/decode` verifies tokens against
+pub struct TrustedKeys {
+    keys: Vec<TrustedKey>,
+}
+
+impl TrustedKeys {
+    pub fn from_env() -> TrustedKeys {
+        // Fall back to the embedded key, which only ever signed RSA tokens
+        let Ok(path) = env::var("GIFT_TRUSTED_KEYS") else {
+            let key = DecodingKey::from_rsa_pem(EMBEDDED_KEY).expect("Invalid embedded key");
+            let algs = vec![Algorithm::RS256, Algorithm::RS384, Algorithm::RS512];
+            return TrustedKeys {
+                keys: vec![TrustedKey {
+                    kid: None,
+                    algs,
+                    key,
+                }],
+            };
+        };
+
+        let keys = load(Path::new(&path)).unwrap_or_else(|err| {
+            println!("Invalid trusted keys: {}", err);
+            Vec::new()
+        });
+        TrustedKeys { keys }
+    }
+
+    /// Keys that may verify a token with this header, the named one if it has a `kid`
+    ///
+    /// Keys without a `kid` of their own, like the embedded one, are tried when no
+    /// key has the named `kid`
+    pub fn candidates(
+        &self,
+        kid: Option<&str>,
+        alg: Algorithm,
+    ) -> Result<Vec<&TrustedKey>, KeyLookupError> {
+        let mut named: Vec<&TrustedKey> = self
+            .keys
+            .iter()
+            .filter(|key| kid.is_none() || key.kid.as_deref() == kid)
+            .collect();
+        if named.is_empty() {
+            named = self.keys.iter().filter(|key| key.kid.is_none()).collect();
+        }
+        if named.is_empty() {
+            return Err(KeyLookupError::UnknownKid);
+        }
+        let allowed: Vec<&TrustedKey> = named
+            .into_iter()
+            .filter(|key| key.algs.contains(&alg))
+            .collect();
+        if allowed.is_empty() {
+            return Err(KeyLookupError::AlgorithmNotAllowed);
+        }
+        Ok(allowed)
+    }
+}
+
+fn load(path: &Path) -> Result<Vec<TrustedKey>, String> {
+    let read = |path: &Path| fs::read_to_string(path).map_err(|err| format!("{:?}: {}", path, err));
+    let sources: Vec<KeySource> =
+        serde_json::from_str(&read(path)?).map_err(|err| err.to_string())?;
+
+    // Files named in the config are relative to it
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let mut keys = Vec::new();
+    for source in sources {
+        match source {
+            KeySource::Pem { kid, pem, algs } => {
+                let pem = read(&dir.join(pem))?;
+                keys.push(from_pem(kid, &pem, algs)?);
+            }
+            KeySource::Jwks { jwks, algs } => {
+                let set: JwkSet =
+                    serde_json::from_str(&read(&dir.join(jwks))?).map_err(|err| err.to_string())?;
+                for jwk in set.keys {
+                    let algs = match (&algs, jwk.common.key_algorithm) {
+                        (Some(algs), _) => algs.clone(),
+                        (None, Some(alg)) => vec![alg
+                            .to_string()
+                            .parse::<Algorithm>()
+                            .map_err(|err| err.to_string())?],
+                        (None, None) => return Err("JWK without alg or algs".to_string()),
+                    };
+                    let key_family = match &jwk.algorithm {
+                        AlgorithmParameters::RSA(_) => Family::Rsa,
+                        AlgorithmParameters::EllipticCurve(_) => Family::Ec,
+                        AlgorithmParameters::OctetKeyPair(p)
+                            if p.curve == EllipticCurve::Ed25519 =>
+                        {
+                            Family::Ed
+                        }
+                        _ => return Err("Unsupported JWK type".to_string()),
+                    };
+                    check_algs(&algs, key_family)?;
+                    keys.push(TrustedKey {
+                        kid: jwk.common.key_id.clone(),
+                        algs,
+                        key: DecodingKey::from_jwk(&jwk).map_err(|err| err.to_string())?,
+                    });
+                }
+            }
+        }
+    }
+    Ok(keys)
+}
+
+fn check_algs(algs: &[Algorithm], key_family: Family) -> Result<(), String> {
+    // Every allowed algorithm has to match the key type, so a token can not pick another
+    match algs.iter().find(|alg| family(**alg) != Some(key_family)) {
+        Some(alg) => Err(format!("{:?} can not be used with this key", alg)),
+        None if algs.is_empty() => Err("No algorithms allowed".to_string()),
+        None => Ok(()),
+    }
+}
+
+fn from_pem(kid: Option<String>, pem: &str, algs: Vec<Algorithm>) -> Result<TrustedKey, String> {
+    let key_family = algs
+        .first()
+        .and_then(|alg| family(*alg))
+        .ok_or("Expected an asymmetric algorithm")?;
+    check_algs(&algs, key_family)?;
+    let key = match key_family {
+        Family::Rsa => DecodingKey::from_rsa_pem(pem.as_bytes()),
+        Family::Ec => DecodingKey::from_ec_pem(pem.as_bytes()),
+        Family::Ed => DecodingKey::from_ed_pem(pem.as_bytes()),
+    }
+    .map_err(|err| err.to_string())?;
+    Ok(TrustedKey { kid, algs, key })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rsa_key(kid: Option<&str>) -> TrustedKey {
+        TrustedKey {
+            kid: kid.map(str::to_string),
+            algs: vec![Algorithm::RS256],
+            key: DecodingKey::from_rsa_pem(EMBEDDED_KEY).unwrap(),
+        }
+    }
+
+    #[test]
+    fn allowed_algorithms() {
+        assert!(check_algs(&[Algorithm::RS256, Algorithm::PS512], Family::Rsa).is_ok());
+        assert!(check_algs(&[Algorithm::ES256], Family::Ec).is_ok());
+        // HMAC would let the public key be used as a shared secret
+        assert!(check_algs(&[Algorithm::HS256], Family::Rsa).is_err());
+        assert!(check_algs(&[Algorithm::RS256, Algorithm::HS256], Family::Rsa).is_err());
+        // Mixed families, or none at all
+        assert!(check_algs(&[Algorithm::RS256, Algorithm::ES256], Family::Rsa).is_err());
+        assert!(check_algs(&[Algorithm::EdDSA], Family::Ec).is_err());
+        assert!(check_algs(&[], Family::Rsa).is_err());
+    }
+
+    #[test]
+    fn pem_algorithms() {
+        let pem = std::str::from_utf8(EMBEDDED_KEY).unwrap();
+        assert!(from_pem(None, pem, vec![Algorithm::RS256]).is_ok());
+        assert!(from_pem(None, pem, vec![Algorithm::HS256]).is_err());
+        assert!(from_pem(None, pem, vec![Algorithm::HS256, Algorithm::RS256]).is_err());
+        assert!(from_pem(None, pem, vec![Algorithm::RS256, Algorithm::HS256]).is_err());
+        assert!(from_pem(None, pem, vec![]).is_err());
+    }
+
+    #[test]
+    fn candidates() {
+        let trusted = TrustedKeys {
+            keys: vec![rsa_key(Some("main")), rsa_key(None)],
+        };
+        let kids = |kid, alg| {
+            trusted
+                .candidates(kid, alg)
+                .map(|keys| keys.iter().map(|k| k.kid.clone()).collect::<Vec<_>>())
+        };
+        assert_eq!(
+            kids(Some("main"), Algorithm::RS256).unwrap(),
+            [Some("main".to_string())]
+        );
+        assert_eq!(kids(None, Algorithm::RS256).unwrap().len(), 2);
+        // Keys without a kid are tried for a kid nobody has
+        assert_eq!(kids(Some("other"), Algorithm::RS256).unwrap(), [None]);
+        assert!(matches!(
+            kids(Some("main"), Algorithm::HS256),
+            Err(KeyLookupError::AlgorithmNotAllowed)
+        ));
+
+        let named = TrustedKeys {
+            keys: vec![rsa_key(Some("main"))],
+        };
+        assert!(matches!(
+            named.candidates(Some("other"), Algorithm::RS256),
+            Err(KeyLookupError::UnknownKid)
+        ));
+    }
+}
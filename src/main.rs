@@ -22,7 +22,13 @@ async fn main(
         "JWT_SECRET",
         secrets.get("JWT_SECRET").expect("JWT_SECRET not set"),
     );
+    for name in challenge5::CONFIG_VARS {
+        if let Some(value) = secrets.get(name) {
+            env::set_var(name, value);
+        }
+    }
 
+    let gifts = challenge5::state(Some(&pool)).await;
     let static_server = ServeDir::new("static");
     let router = Router::new()
         .route("/", get(hello_world))
@@ -30,10 +36,11 @@ async fn main(
         .nest("/2", challenge1::router())
         .nest("/5", challenge2::router())
         .nest("/9", challenge3::router())
-        .nest("/12", challenge4::router())
-        .nest("/16", challenge5::router())
+        .nest("/12", challenge4::router(&pool).await)
+        .nest("/16", challenge5::router(&gifts))
         .nest("/19", challenge6::router(&pool).await)
         .nest("/23", challenge7::router())
+        .nest("/.well-known", challenge5::well_known(&gifts))
         .nest_service("/assets", static_server);
 
     Ok(router.into())